@@ -0,0 +1,101 @@
+//! Compares the indexing throughput of the folder traversals on a generated tree.
+//!
+//! ```bash
+//! cargo run --release --example traversal_benchmark -- [folders] [files_per_folder] [large_folder_files]
+//! ```
+//!
+//! The tree is nested a few levels deep, with one large folder next to many small ones,
+//! which is where a barrier per depth level hurts the most.
+
+use rust_folder_analysis::indexing::index_creation::create_index;
+use rust_folder_analysis::indexing::index_options::{IndexOptions, Traversal};
+
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Folders per nesting level of the generated tree.
+const FANOUT: usize = 8;
+
+/// Runs per traversal; the best run is reported.
+const RUNS: usize = 3;
+
+fn argument(position: usize, default: usize) -> usize {
+    env::args()
+        .nth(position)
+        .map(|arg| arg.parse().expect("Arguments should be numbers"))
+        .unwrap_or(default)
+}
+
+/// Creates `folders` nested folders with `files_per_folder` files each, plus one folder with `large_folder_files` files.
+fn generate_tree(root: &Path, folders: usize, files_per_folder: usize, large_folder_files: usize) {
+    for folder in 0..folders {
+        // The base-8 digits of the folder number, lowest first: folder 123 (0o173) ends up in 3/7/1,
+        // below folder 59 (0o73) in 3/7, so the tree gets deeper as it grows.
+        let mut folder_path = root.to_path_buf();
+        let mut rest = folder;
+        loop {
+            folder_path.push((rest % FANOUT).to_string());
+            rest /= FANOUT;
+            if rest == 0 {
+                break;
+            }
+        }
+
+        create_dir_all(&folder_path).expect("Failed to create folder");
+        for file in 0..files_per_folder {
+            write(folder_path.join(format!("file_{}.txt", file)), b"benchmark")
+                .expect("Failed to create file");
+        }
+    }
+
+    let large_folder = root.join("large");
+    create_dir_all(&large_folder).expect("Failed to create folder");
+    for file in 0..large_folder_files {
+        write(
+            large_folder.join(format!("file_{}.txt", file)),
+            b"benchmark",
+        )
+        .expect("Failed to create file");
+    }
+}
+
+fn main() {
+    let folders = argument(1, 2_000);
+    let files_per_folder = argument(2, 20);
+    let large_folder_files = argument(3, 50_000);
+
+    let root: PathBuf = env::temp_dir().join("rust-folder-analysis-benchmark");
+    let _ = remove_dir_all(&root);
+
+    println!(
+        "Generating {} folders with {} files each, and one folder with {} files in {:?}",
+        folders, files_per_folder, large_folder_files, root
+    );
+    generate_tree(&root, folders, files_per_folder, large_folder_files);
+
+    for (name, traversal) in [
+        ("level", Traversal::LevelByLevel),
+        ("work-stealing", Traversal::WorkStealing),
+    ] {
+        let options = IndexOptions {
+            get_metadata: true,
+            traversal,
+            ..Default::default()
+        };
+
+        let best = (0..RUNS)
+            .map(|_| {
+                let start = Instant::now();
+                let summary =
+                    create_index(&root, &options, None, |_| true).expect("Failed to index");
+                summary.path_count as f64 / start.elapsed().as_secs_f64()
+            })
+            .fold(0.0, f64::max);
+
+        println!("{:>14}: {:>10.0} paths/s", name, best);
+    }
+
+    remove_dir_all(&root).expect("Failed to remove the generated tree");
+}
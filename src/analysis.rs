@@ -1 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod analysis;
+pub mod diff;
+pub mod html_report;
+pub mod treemap;
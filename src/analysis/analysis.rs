@@ -5,36 +5,139 @@ use polars::prelude::*;
 #[allow(unused)]
 use log::{error, info, warn};
 
-use crate::utils::{file_operations::print_and_save, hashing::hash_iterable};
+use crate::analysis::html_report::save_html_report;
+use crate::analysis::treemap::{save_treemap, TREEMAP_HEIGHT, TREEMAP_WIDTH};
+use crate::browse::file_tree::FileTree;
+use crate::error::Error;
+use crate::indexing::folder_index::folder_index;
+use crate::utils::file_operations::{print_and_save, ResultFormat};
 
 const BYTES_TO_MB: u64 = 1024 * 1024;
 const BYTES_TO_GB: u64 = 1024 * 1024 * 1024;
 
-fn total_folder_size(df: &DataFrame) -> u64 {
-    df.column("size")
-        .expect("Failed to get size column")
-        .u64()
-        .expect("Failed to convert to u64")
-        .sum()
-        .expect("Failed to sum")
-        / BYTES_TO_GB
+/// Which size the analysis ranks files and folders by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeMetric {
+    /// The file length, as shown by `ls -l`.
+    #[default]
+    Apparent,
+    /// The space allocated on disk, as shown by `du`. Smaller for sparse and compressed files.
+    Allocated,
 }
 
-fn top_n_file_sizes(df: &DataFrame, top_n: u32) -> DataFrame {
+impl SizeMetric {
+    /// Name of the column in the results that is sorted on.
+    fn sort_column(&self) -> &'static str {
+        match self {
+            SizeMetric::Apparent => "size (MB)",
+            SizeMetric::Allocated => "allocated (MB)",
+        }
+    }
+}
+
+/// Options controlling which analysis results are produced.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnalysisOptions {
+    /// Print the overall hash of all files, the Merkle hash of the root folder.
+    pub get_hash: bool,
+    /// Size used to rank files and folders.
+    pub size_metric: SizeMetric,
+    /// Render a treemap of the folder sizes to SVG and PNG.
+    pub treemap: bool,
+    /// Save all results in a single, self-contained HTML report.
+    pub html_report: bool,
+    /// File format of the saved results.
+    pub format: ResultFormat,
+}
+
+impl std::str::FromStr for SizeMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "apparent" => Ok(SizeMetric::Apparent),
+            "allocated" => Ok(SizeMetric::Allocated),
+            _ => Err(format!("Unknown size metric: {}", s)),
+        }
+    }
+}
+
+/// Adds `unique_size`: hardlinks share their data, so only one path per inode keeps its size.
+fn with_unique_size(df: &DataFrame) -> LazyFrame {
+    let lf = df.clone().lazy();
+
+    let inode_owners = lf
+        .clone()
+        .filter(col("link_count").gt(lit(1)))
+        .group_by([col("device"), col("inode")])
+        .agg([col("path").min().alias("inode_owner")]);
+
+    lf.join(
+        inode_owners,
+        [col("device"), col("inode")],
+        [col("device"), col("inode")],
+        JoinArgs::new(JoinType::Left),
+    )
+    .with_column(
+        when(
+            col("inode_owner")
+                .is_null()
+                .or(col("inode_owner").eq(col("path"))),
+        )
+        .then(col("size"))
+        .otherwise(lit(0).cast(DataType::UInt64))
+        .alias("unique_size"),
+    )
+}
+
+/// Apparent size, unique size counting hardlinked files once, and allocated size.
+fn total_folder_size(df: &DataFrame) -> PolarsResult<(u64, u64, u64)> {
+    let totals = with_unique_size(df)
+        .select([
+            col("size").sum(),
+            col("unique_size").sum(),
+            col("allocated_size").sum(),
+        ])
+        .collect()?;
+
+    let total = |column: &str| -> PolarsResult<u64> {
+        Ok(totals.column(column)?.u64()?.get(0).unwrap_or(0) / BYTES_TO_GB)
+    };
+
+    Ok((
+        total("size")?,
+        total("unique_size")?,
+        total("allocated_size")?,
+    ))
+}
+
+fn top_n_file_sizes(
+    df: &DataFrame,
+    top_n: u32,
+    size_metric: SizeMetric,
+) -> PolarsResult<DataFrame> {
     df.clone()
         .lazy()
-        .with_columns([(col("size") / lit(BYTES_TO_MB)).alias("size (MB)")])
-        .select([col("name"), col("size (MB)"), col("extension"), col("path")])
+        .with_columns([
+            (col("size") / lit(BYTES_TO_MB)).alias("size (MB)"),
+            (col("allocated_size") / lit(BYTES_TO_MB)).alias("allocated (MB)"),
+        ])
+        .select([
+            col("name"),
+            col("size (MB)"),
+            col("allocated (MB)"),
+            col("extension"),
+            col("path"),
+        ])
         .sort(
-            ["size (MB)"],
+            [size_metric.sort_column()],
             SortMultipleOptions::new().with_order_descending(true),
         )
         .limit(top_n)
         .collect()
-        .expect("Failed to sort DataFrame by size")
 }
 
-fn file_size_per_extension(df: &DataFrame) -> DataFrame {
+fn file_size_per_extension(df: &DataFrame) -> PolarsResult<DataFrame> {
     df.clone()
         .lazy()
         .group_by([col("extension")])
@@ -46,10 +149,9 @@ fn file_size_per_extension(df: &DataFrame) -> DataFrame {
             SortMultipleOptions::new().with_order_descending(true),
         )
         .collect()
-        .expect("Failed to group")
 }
 
-fn extension_counts(df: &DataFrame) -> DataFrame {
+fn extension_counts(df: &DataFrame) -> PolarsResult<DataFrame> {
     df.clone()
         .lazy()
         .group_by([col("extension")])
@@ -59,73 +161,341 @@ fn extension_counts(df: &DataFrame) -> DataFrame {
             SortMultipleOptions::new().with_order_descending(true),
         )
         .collect()
-        .expect("Failed to count file extensions.")
 }
 
-fn largest_folders(df: &DataFrame) -> DataFrame {
-    df.clone()
-        .lazy()
+fn largest_folders(df: &DataFrame, size_metric: SizeMetric) -> PolarsResult<DataFrame> {
+    with_unique_size(df)
         .group_by([col("parents")])
-        .agg([col("size").sum().alias("total_size")])
-        .with_column((col("total_size") / lit(BYTES_TO_MB)).alias("size (MB)"))
-        .select([col("parents"), col("size (MB)")])
+        .agg([
+            col("size").sum().alias("total_size"),
+            col("unique_size").sum().alias("total_unique_size"),
+            col("allocated_size").sum().alias("total_allocated_size"),
+        ])
+        .with_columns([
+            (col("total_size") / lit(BYTES_TO_MB)).alias("size (MB)"),
+            (col("total_unique_size") / lit(BYTES_TO_MB)).alias("unique size (MB)"),
+            (col("total_allocated_size") / lit(BYTES_TO_MB)).alias("allocated (MB)"),
+        ])
+        .select([
+            col("parents"),
+            col("size (MB)"),
+            col("unique size (MB)"),
+            col("allocated (MB)"),
+        ])
         .sort(
-            ["size (MB)"],
+            [size_metric.sort_column()],
             SortMultipleOptions::new().with_order_descending(true),
         )
         .collect()
-        .expect("Failed to sum by parents")
 }
 
-fn overall_hash(df: &DataFrame) -> String {
-    let hash_column = df
-        .column("hash")
-        .expect("Failed to extract hash column")
-        .str()
-        .expect("Failed to convert to str");
+/// Folders of the folder index by the total size of everything below them, including subfolders.
+fn cumulative_folder_sizes(
+    folders: &DataFrame,
+    size_metric: SizeMetric,
+) -> PolarsResult<DataFrame> {
+    folders
+        .clone()
+        .lazy()
+        .with_columns([
+            (col("total_size") / lit(BYTES_TO_MB)).alias("size (MB)"),
+            (col("allocated_size") / lit(BYTES_TO_MB)).alias("allocated (MB)"),
+        ])
+        .select([
+            col("path"),
+            col("depth"),
+            col("size (MB)"),
+            col("allocated (MB)"),
+            col("file_count"),
+            col("subfolder_count"),
+        ])
+        .sort(
+            [size_metric.sort_column()],
+            SortMultipleOptions::new().with_order_descending(true),
+        )
+        .collect()
+}
 
-    let hash_vector: Vec<&str> = hash_column.into_no_null_iter().collect();
+/// Files sharing a hash and size, one row per file, with the bytes wasted by the extra copies.
+/// Empty files are left out, as they waste nothing.
+fn duplicate_files(df: &DataFrame) -> PolarsResult<DataFrame> {
+    let files = df.clone().lazy().filter(
+        col("is_folder")
+            .not()
+            .and(col("hash").is_not_null())
+            .and(col("size").gt(lit(0))),
+    );
 
-    let overall_hash = hash_iterable(hash_vector);
-    println!("File hash: {}", overall_hash);
+    files
+        .clone()
+        .group_by([col("hash"), col("size")])
+        .agg([col("path").count().alias("count")])
+        .filter(col("count").gt(lit(1)))
+        .with_column(
+            (col("size") * col("count").cast(DataType::UInt64) - col("size")).alias("wasted_bytes"),
+        )
+        .join(
+            files.select([col("hash"), col("size"), col("path")]),
+            [col("hash"), col("size")],
+            [col("hash"), col("size")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([
+            col("hash"),
+            col("count"),
+            col("size"),
+            col("wasted_bytes"),
+            col("path"),
+        ])
+        .sort(
+            ["wasted_bytes", "hash", "path"],
+            SortMultipleOptions::new().with_order_descending_multi([true, false, false]),
+        )
+        .collect()
+}
 
-    overall_hash
+/// Total bytes that could be freed by keeping a single copy of each duplicate set.
+fn wasted_bytes(duplicates: &DataFrame) -> PolarsResult<u64> {
+    Ok(duplicates
+        .clone()
+        .lazy()
+        .group_by([col("hash")])
+        .agg([col("wasted_bytes").first()])
+        .select([col("wasted_bytes").sum()])
+        .collect()?
+        .column("wasted_bytes")?
+        .u64()?
+        .get(0)
+        .unwrap_or(0))
+}
+
+/// Merkle hash of the indexed root from the folder index, which is the same for identical trees.
+/// None if any file is missing a hash.
+fn overall_hash(folders: &DataFrame) -> PolarsResult<Option<String>> {
+    // The root is always the first folder.
+    let overall_hash = folders
+        .column("merkle_hash")?
+        .str()?
+        .get(0)
+        .map(str::to_string);
+
+    match &overall_hash {
+        Some(overall_hash) => println!("Overall hash: {}", overall_hash),
+        None => warn!("Not every file has a hash, so there is no overall hash."),
+    }
+
+    Ok(overall_hash)
+}
+
+/// Columns of the path index used by the analysis.
+const ANALYSIS_COLUMNS: [&str; 14] = [
+    "path",
+    "parents",
+    "name",
+    "extension",
+    "size",
+    "allocated_size",
+    "modified",
+    "is_folder",
+    "hash",
+    "file_type",
+    "link_target",
+    "device",
+    "inode",
+    "link_count",
+];
+
+/// Checks that a cache loaded from disk can be analysed: all columns must exist, the files need
+/// sizes (so the index was built with metadata), and hashes if the overall hash is requested.
+pub fn check_analysis_cache(df: &DataFrame, options: &AnalysisOptions) -> Result<(), Error> {
+    for column in ANALYSIS_COLUMNS {
+        if df.column(column).is_err() {
+            return Err(Error::InvalidCache(format!(
+                "cache has no '{}' column: it was created by an older version, index the folder again",
+                column
+            )));
+        }
+    }
+
+    let files = df
+        .clone()
+        .lazy()
+        // Symlinks and special files are never hashed.
+        .filter(col("file_type").eq(lit("file")))
+        .select([
+            len().alias("files"),
+            col("size").null_count().alias("missing_sizes"),
+            col("hash").null_count().alias("missing_hashes"),
+        ])
+        .collect()?;
+
+    let count = |name: &str| -> PolarsResult<u64> {
+        Ok(files
+            .column(name)?
+            .cast(&DataType::UInt64)?
+            .u64()?
+            .get(0)
+            .unwrap_or(0))
+    };
+    let (file_count, missing_sizes, missing_hashes) = (
+        count("files")?,
+        count("missing_sizes")?,
+        count("missing_hashes")?,
+    );
+
+    if file_count > 0 && missing_sizes == file_count {
+        return Err(Error::InvalidCache(
+            "cache has no file sizes: it was indexed without metadata, index the folder again with -m"
+                .to_string(),
+        ));
+    }
+    if options.get_hash && missing_hashes > 0 {
+        return Err(Error::InvalidCache(format!(
+            "{} of {} files in the cache have no hash: index the folder again with -H",
+            missing_hashes, file_count
+        )));
+    }
+
+    Ok(())
 }
 
 /// Some simple analysis options. Fun way to explore Polars.
-pub fn run_analysis(df: DataFrame, analysis_folder_path: &Path, get_hash: bool) {
-    let total_folder_size: u64 = total_folder_size(&df);
+pub fn run_analysis(
+    df: DataFrame,
+    analysis_folder_path: &Path,
+    options: &AnalysisOptions,
+) -> Result<(), Error> {
+    let size_metric = options.size_metric;
+
+    let (total_folder_size, unique_folder_size, allocated_folder_size) = total_folder_size(&df)?;
 
     let top_n = 100;
 
-    info!("Total folder size: {} GB", total_folder_size);
+    info!(
+        "Total folder size: {} GB ({} GB counting hardlinked files once, {} GB allocated on disk)",
+        total_folder_size, unique_folder_size, allocated_folder_size
+    );
+
+    let mut top_n_file_sizes = top_n_file_sizes(&df, top_n, size_metric)?;
+    let mut file_size_per_extension = file_size_per_extension(&df)?;
+    let mut extension_counts = extension_counts(&df)?;
+    let mut largest_folders = largest_folders(&df, size_metric)?;
+    // The folders table, the overall hash and the tree all use the same folder totals.
+    let folders = folder_index(&df)?;
+    let mut cumulative_folder_sizes = cumulative_folder_sizes(&folders, size_metric)?;
 
     print_and_save(
-        &mut top_n_file_sizes(&df, top_n),
-        &analysis_folder_path,
-        "top_n_file_sizes.csv",
+        &mut top_n_file_sizes,
+        analysis_folder_path,
+        &options.format.file_name("top_n_file_sizes"),
         "Top n files by size",
-    );
+        options.format,
+    )?;
     print_and_save(
-        &mut file_size_per_extension(&df),
-        &analysis_folder_path,
-        "file_size_per_extension.csv",
+        &mut file_size_per_extension,
+        analysis_folder_path,
+        &options.format.file_name("file_size_per_extension"),
         "File sizes per extension",
-    );
+        options.format,
+    )?;
     print_and_save(
-        &mut extension_counts(&df),
-        &analysis_folder_path,
-        "extension_counts.csv",
+        &mut extension_counts,
+        analysis_folder_path,
+        &options.format.file_name("extension_counts"),
         "Extension counts",
-    );
+        options.format,
+    )?;
     print_and_save(
-        &mut largest_folders(&df),
-        &analysis_folder_path,
-        "largest_folders.csv",
+        &mut largest_folders,
+        analysis_folder_path,
+        &options.format.file_name("largest_folders"),
         "Folders by size",
-    );
+        options.format,
+    )?;
+    print_and_save(
+        &mut cumulative_folder_sizes,
+        analysis_folder_path,
+        &options.format.file_name("cumulative_folder_sizes"),
+        "Folders by size, including subfolders",
+        options.format,
+    )?;
+
+    // Hashes can also be present only for files with a matching size, which is enough for duplicates.
+    let hash_count = df.height()
+        - df.column("hash")
+            .map_or(df.height(), |hash| hash.null_count());
+    let duplicates = if hash_count > 0 {
+        let mut duplicates = duplicate_files(&df)?;
+
+        info!(
+            "Duplicate files waste {} MB",
+            wasted_bytes(&duplicates)? / BYTES_TO_MB
+        );
+
+        print_and_save(
+            &mut duplicates,
+            analysis_folder_path,
+            &options.format.file_name("duplicates"),
+            "Duplicate files",
+            options.format,
+        )?;
+        Some(duplicates)
+    } else {
+        None
+    };
 
-    if get_hash {
-        overall_hash(&df);
+    let overall_hash = if options.get_hash {
+        overall_hash(&folders)?
+    } else {
+        None
+    };
+
+    if !options.treemap && !options.html_report {
+        return Ok(());
     }
+
+    let tree = FileTree::from_df(&df, &folders)?;
+
+    if options.treemap {
+        save_treemap(&tree, analysis_folder_path, TREEMAP_WIDTH, TREEMAP_HEIGHT)?;
+    }
+
+    if options.html_report {
+        let mut totals = vec![
+            ("Total size (GB)", total_folder_size.to_string()),
+            (
+                "Counting hardlinked files once (GB)",
+                unique_folder_size.to_string(),
+            ),
+            ("Allocated on disk (GB)", allocated_folder_size.to_string()),
+            ("Paths", df.height().to_string()),
+        ];
+        if let Some(duplicates) = &duplicates {
+            totals.push((
+                "Wasted by duplicates (MB)",
+                (wasted_bytes(duplicates)? / BYTES_TO_MB).to_string(),
+            ));
+        }
+        if let Some(overall_hash) = overall_hash {
+            totals.push(("Overall hash", overall_hash));
+        }
+
+        let mut tables = vec![
+            ("Top files by size", &top_n_file_sizes),
+            ("File sizes per extension", &file_size_per_extension),
+            ("Extension counts", &extension_counts),
+            (
+                "Largest folders, including subfolders",
+                &cumulative_folder_sizes,
+            ),
+            ("Folders by size of their own files", &largest_folders),
+        ];
+        if let Some(duplicates) = &duplicates {
+            tables.push(("Duplicate files", duplicates));
+        }
+
+        save_html_report(&totals, &tables, &tree, analysis_folder_path)?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path};
+
+use polars::prelude::*;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use crate::error::Error;
+use crate::indexing::folder_index::index_root;
+use crate::utils::file_operations::{print_and_save, ResultFormat};
+
+/// What happened to a path between two index snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+    ContentChanged,
+    Renamed,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Modified => "modified",
+            Change::ContentChanged => "content_changed",
+            Change::Renamed => "renamed",
+        }
+    }
+}
+
+/// The columns of a single cached path needed for comparisons.
+#[derive(Debug, Clone)]
+struct SnapshotEntry {
+    size: Option<u64>,
+    modified: Option<i64>,
+    is_folder: bool,
+    hash: Option<String>,
+    hash_algorithm: Option<String>,
+}
+
+impl SnapshotEntry {
+    /// Hash together with its algorithm, as hashes of different algorithms can't be compared.
+    /// Empty files all share the same hash, so they get no key and are never matched as renames.
+    fn hash_key(&self) -> Option<(&str, &str)> {
+        if self.size == Some(0) {
+            return None;
+        }
+        Some((self.hash_algorithm.as_deref()?, self.hash.as_deref()?))
+    }
+}
+
+/// A single difference between the old and the new snapshot.
+#[derive(Debug)]
+struct PathChange {
+    change: Change,
+    path: String,
+    old_path: Option<String>,
+    old_size: Option<u64>,
+    new_size: Option<u64>,
+}
+
+/// Reads the cache DataFrame into a map keyed by path.
+fn snapshot_entries(df: &DataFrame) -> Result<HashMap<String, SnapshotEntry>, PolarsError> {
+    let paths = df.column("path")?.str()?;
+    let sizes = df.column("size")?.u64()?;
+    let modified = df.column("modified")?.i64()?;
+    let is_folders = df.column("is_folder")?.bool()?;
+    let hashes = df.column("hash")?.str()?;
+    let hash_algorithms = df.column("hash_algorithm")?.str()?;
+
+    let entries = paths
+        .into_iter()
+        .zip(sizes)
+        .zip(modified)
+        .zip(is_folders)
+        .zip(hashes)
+        .zip(hash_algorithms)
+        .filter_map(
+            |(((((path, size), modified), is_folder), hash), hash_algorithm)| {
+                path.map(|path| {
+                    (
+                        path.to_string(),
+                        SnapshotEntry {
+                            size,
+                            modified,
+                            is_folder: is_folder.unwrap_or(false),
+                            hash: hash.map(str::to_string),
+                            hash_algorithm: hash_algorithm.map(str::to_string),
+                        },
+                    )
+                })
+            },
+        )
+        .collect();
+
+    Ok(entries)
+}
+
+/// First folder below the root that contains the path, or "." for paths directly in the root.
+fn top_level_folder(path: &str, root: &str) -> String {
+    let relative = Path::new(path)
+        .strip_prefix(root)
+        .unwrap_or(Path::new(path));
+    let mut components = relative.components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(folder)), Some(_)) => folder.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    }
+}
+
+/// Compares two snapshots: added, removed, modified (size or mtime changed), content-changed
+/// (hash differs) and renamed paths, where renames are removed and added files with the same hash.
+/// Hashes are only compared when both snapshots used the same hash algorithm.
+fn compare_snapshots(
+    old: &HashMap<String, SnapshotEntry>,
+    new: &HashMap<String, SnapshotEntry>,
+) -> Vec<PathChange> {
+    let mut changes = Vec::new();
+
+    let mut removed: Vec<(&String, &SnapshotEntry)> = old
+        .iter()
+        .filter(|(path, _)| !new.contains_key(*path))
+        .collect();
+    let mut added: Vec<(&String, &SnapshotEntry)> = new
+        .iter()
+        .filter(|(path, _)| !old.contains_key(*path))
+        .collect();
+
+    // Sorting so renames are matched the same way on every run.
+    removed.sort_by(|a, b| a.0.cmp(b.0));
+    added.sort_by(|a, b| a.0.cmp(b.0));
+
+    // Candidate sources for renames, grouped by hash.
+    let mut removed_by_hash: HashMap<(&str, &str), Vec<&String>> = HashMap::new();
+    for (path, entry) in removed.iter().rev() {
+        if let (false, Some(key)) = (entry.is_folder, entry.hash_key()) {
+            removed_by_hash.entry(key).or_default().push(path);
+        }
+    }
+
+    let mut renamed_from = HashSet::new();
+    for (path, entry) in &added {
+        let old_path = match (entry.is_folder, entry.hash_key()) {
+            (false, Some(key)) => removed_by_hash.get_mut(&key).and_then(|paths| paths.pop()),
+            _ => None,
+        };
+
+        match old_path {
+            Some(old_path) => {
+                renamed_from.insert(old_path);
+                changes.push(PathChange {
+                    change: Change::Renamed,
+                    path: path.to_string(),
+                    old_path: Some(old_path.to_string()),
+                    old_size: old[old_path].size,
+                    new_size: entry.size,
+                });
+            }
+            None => changes.push(PathChange {
+                change: Change::Added,
+                path: path.to_string(),
+                old_path: None,
+                old_size: None,
+                new_size: entry.size,
+            }),
+        }
+    }
+
+    for (path, entry) in &removed {
+        if !renamed_from.contains(path) {
+            changes.push(PathChange {
+                change: Change::Removed,
+                path: path.to_string(),
+                old_path: None,
+                old_size: entry.size,
+                new_size: None,
+            });
+        }
+    }
+
+    for (path, new_entry) in new {
+        let Some(old_entry) = old.get(path) else {
+            continue;
+        };
+        if old_entry.is_folder || new_entry.is_folder {
+            continue;
+        }
+
+        let change = match (&old_entry.hash, &new_entry.hash) {
+            (Some(old_hash), Some(new_hash))
+                if old_entry.hash_algorithm == new_entry.hash_algorithm && old_hash != new_hash =>
+            {
+                Some(Change::ContentChanged)
+            }
+            _ if old_entry.size != new_entry.size || old_entry.modified != new_entry.modified => {
+                Some(Change::Modified)
+            }
+            _ => None,
+        };
+
+        if let Some(change) = change {
+            changes.push(PathChange {
+                change,
+                path: path.to_string(),
+                old_path: None,
+                old_size: old_entry.size,
+                new_size: new_entry.size,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| (a.change, &a.path).cmp(&(b.change, &b.path)));
+    changes
+}
+
+fn size_delta(old_size: Option<u64>, new_size: Option<u64>) -> i64 {
+    new_size.unwrap_or(0) as i64 - old_size.unwrap_or(0) as i64
+}
+
+/// All changed paths between two caches as a DataFrame.
+pub fn diff_caches(old_df: &DataFrame, new_df: &DataFrame) -> Result<DataFrame, PolarsError> {
+    let changes = compare_snapshots(&snapshot_entries(old_df)?, &snapshot_entries(new_df)?);
+
+    let change: Vec<&str> = changes.iter().map(|c| c.change.as_str()).collect();
+    let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+    let old_paths: Vec<Option<&str>> = changes.iter().map(|c| c.old_path.as_deref()).collect();
+    let old_sizes: Vec<Option<u64>> = changes.iter().map(|c| c.old_size).collect();
+    let new_sizes: Vec<Option<u64>> = changes.iter().map(|c| c.new_size).collect();
+    let deltas: Vec<i64> = changes
+        .iter()
+        .map(|c| size_delta(c.old_size, c.new_size))
+        .collect();
+
+    DataFrame::new(vec![
+        Series::new("change", change),
+        Series::new("path", paths),
+        Series::new("old_path", old_paths),
+        Series::new("old_size", old_sizes),
+        Series::new("new_size", new_sizes),
+        Series::new("size_delta", deltas),
+    ])
+}
+
+/// Total file bytes per top-level folder in both snapshots, with the difference.
+pub fn size_delta_per_folder(
+    old_df: &DataFrame,
+    new_df: &DataFrame,
+) -> Result<DataFrame, PolarsError> {
+    let mut totals: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+
+    for (df, is_new) in [(old_df, false), (new_df, true)] {
+        let root = index_root(df)?;
+
+        for (path, entry) in snapshot_entries(df)? {
+            if entry.is_folder {
+                continue;
+            }
+            let total = totals.entry(top_level_folder(&path, &root)).or_default();
+            if is_new {
+                total.1 += entry.size.unwrap_or(0);
+            } else {
+                total.0 += entry.size.unwrap_or(0);
+            }
+        }
+    }
+
+    let folders: Vec<&str> = totals.keys().map(String::as_str).collect();
+    let old_sizes: Vec<u64> = totals.values().map(|total| total.0).collect();
+    let new_sizes: Vec<u64> = totals.values().map(|total| total.1).collect();
+    let deltas: Vec<i64> = totals
+        .values()
+        .map(|total| size_delta(Some(total.0), Some(total.1)))
+        .collect();
+
+    DataFrame::new(vec![
+        Series::new("folder", folders),
+        Series::new("old_size", old_sizes),
+        Series::new("new_size", new_sizes),
+        Series::new("size_delta", deltas),
+    ])?
+    .lazy()
+    .sort(
+        ["size_delta"],
+        SortMultipleOptions::new().with_order_descending(true),
+    )
+    .collect()
+}
+
+/// Compares two caches, logging a summary and saving the differences.
+pub fn run_diff(
+    old_df: &DataFrame,
+    new_df: &DataFrame,
+    analysis_folder_path: &Path,
+    format: ResultFormat,
+) -> Result<(), Error> {
+    let mut changes = diff_caches(old_df, new_df)?;
+
+    let change_counts = changes
+        .clone()
+        .lazy()
+        .group_by([col("change")])
+        .agg([
+            col("path").count().alias("count"),
+            col("size_delta").sum().alias("size_delta"),
+        ])
+        .sort(["change"], SortMultipleOptions::default())
+        .collect()?;
+
+    info!("Changes between caches: {:?}", change_counts);
+
+    print_and_save(
+        &mut changes,
+        analysis_folder_path,
+        &format.file_name("diff"),
+        "Changed paths",
+        format,
+    )?;
+    print_and_save(
+        &mut size_delta_per_folder(old_df, new_df)?,
+        analysis_folder_path,
+        &format.file_name("diff_summary"),
+        "Size change per top-level folder",
+        format,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache of files with their size, modification time and SHA256 hash, plus a folder.
+    fn snapshot(files: &[(&str, u64, i64, Option<&str>)]) -> DataFrame {
+        let mut paths = vec!["/data/sub"];
+        let mut sizes = vec![Some(4096)];
+        let mut modified = vec![Some(1)];
+        let mut is_folders = vec![true];
+        let mut hashes = vec![None];
+        for (path, size, mtime, hash) in files {
+            paths.push(path);
+            sizes.push(Some(*size));
+            modified.push(Some(*mtime));
+            is_folders.push(false);
+            hashes.push(*hash);
+        }
+        let algorithms: Vec<Option<&str>> =
+            hashes.iter().map(|hash| hash.map(|_| "sha256")).collect();
+
+        df!(
+            "path" => paths,
+            "size" => sizes,
+            "modified" => modified,
+            "is_folder" => is_folders,
+            "hash" => hashes,
+            "hash_algorithm" => algorithms
+        )
+        .unwrap()
+    }
+
+    /// The change, path and old path of every row.
+    fn changes(old: &DataFrame, new: &DataFrame) -> Vec<(String, String, Option<String>)> {
+        let df = diff_caches(old, new).unwrap();
+        let change = df.column("change").unwrap().str().unwrap();
+        let path = df.column("path").unwrap().str().unwrap();
+        let old_path = df.column("old_path").unwrap().str().unwrap();
+
+        (0..df.height())
+            .map(|row| {
+                (
+                    change.get(row).unwrap().to_string(),
+                    path.get(row).unwrap().to_string(),
+                    old_path.get(row).map(str::to_string),
+                )
+            })
+            .collect()
+    }
+
+    fn change(
+        change: &str,
+        path: &str,
+        old_path: Option<&str>,
+    ) -> (String, String, Option<String>) {
+        (
+            change.to_string(),
+            path.to_string(),
+            old_path.map(str::to_string),
+        )
+    }
+
+    #[test]
+    fn moved_files_are_renames() {
+        let old = snapshot(&[
+            ("/data/a.txt", 10, 1, Some("aa11")),
+            ("/data/gone.txt", 5, 1, Some("bb22")),
+        ]);
+        let new = snapshot(&[
+            ("/data/sub/a.txt", 10, 2, Some("aa11")),
+            ("/data/new.txt", 5, 1, Some("cc33")),
+        ]);
+
+        assert_eq!(
+            changes(&old, &new),
+            vec![
+                change("added", "/data/new.txt", None),
+                change("removed", "/data/gone.txt", None),
+                change("renamed", "/data/sub/a.txt", Some("/data/a.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_files_are_never_renames() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let old = snapshot(&[("/data/empty", 0, 1, Some(empty))]);
+        let new = snapshot(&[("/data/other_empty", 0, 1, Some(empty))]);
+
+        assert_eq!(
+            changes(&old, &new),
+            vec![
+                change("added", "/data/other_empty", None),
+                change("removed", "/data/empty", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_size_edits_are_content_changes_if_hashed() {
+        let old = snapshot(&[
+            ("/data/edited", 10, 1, Some("aa11")),
+            ("/data/touched", 10, 1, Some("bb22")),
+            ("/data/unhashed", 10, 1, None),
+            ("/data/same", 10, 1, Some("cc33")),
+        ]);
+        let new = snapshot(&[
+            ("/data/edited", 10, 1, Some("dd44")),
+            ("/data/touched", 10, 2, Some("bb22")),
+            ("/data/unhashed", 10, 2, None),
+            ("/data/same", 10, 1, Some("cc33")),
+        ]);
+
+        assert_eq!(
+            changes(&old, &new),
+            vec![
+                change("modified", "/data/touched", None),
+                change("modified", "/data/unhashed", None),
+                change("content_changed", "/data/edited", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn hashes_of_other_algorithms_are_not_compared() {
+        let old = snapshot(&[("/data/a", 10, 1, Some("aa11"))]);
+        let mut new = snapshot(&[("/data/a", 10, 1, Some("bb22"))]);
+        new.with_column(Series::new("hash_algorithm", [None, Some("xxh3")]))
+            .unwrap();
+
+        assert!(changes(&old, &new).is_empty());
+    }
+}
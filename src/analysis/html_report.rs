@@ -0,0 +1,251 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt::Write as _;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use polars::prelude::*;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use crate::browse::file_tree::{FileTree, SortOrder};
+use crate::error::{Error, Result};
+use crate::utils::formatting::format_size;
+
+/// Rows shown per table, so large results such as duplicates keep the report small enough to email.
+const MAX_TABLE_ROWS: usize = 200;
+
+/// Files and folders shown in the tree, however large the index is.
+const MAX_TREE_NODES: usize = 5000;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; font-size: 0.9em; }
+th, td { border: 1px solid #ddd; padding: 0.2em 0.6em; text-align: left; }
+th { background: #f0f0f0; }
+tr:nth-child(even) td { background: #fafafa; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+details { margin-left: 1.2em; }
+summary { cursor: pointer; }
+.size { display: inline-block; min-width: 7em; text-align: right; margin-right: 1em; font-variant-numeric: tabular-nums; }
+.file { margin-left: 2.4em; }
+.note { color: #777; font-size: 0.9em; }
+";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn cell_text(value: AnyValue) -> String {
+    match value {
+        AnyValue::Null => String::new(),
+        AnyValue::String(value) => value.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Writes a DataFrame as an HTML table, showing at most `MAX_TABLE_ROWS` rows.
+fn write_table(html: &mut String, df: &DataFrame) {
+    html.push_str("<table>\n<tr>");
+    for name in df.get_column_names() {
+        let _ = write!(html, "<th>{}</th>", escape_html(name));
+    }
+    html.push_str("</tr>\n");
+
+    for row in 0..df.height().min(MAX_TABLE_ROWS) {
+        html.push_str("<tr>");
+        for column in df.get_columns() {
+            let class = if column.dtype().is_numeric() {
+                " class=\"number\""
+            } else {
+                ""
+            };
+            let value = column.get(row).map(cell_text).unwrap_or_default();
+            let _ = write!(html, "<td{}>{}</td>", class, escape_html(&value));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    if df.height() > MAX_TABLE_ROWS {
+        let _ = writeln!(
+            html,
+            "<p class=\"note\">Showing {} of {} rows.</p>",
+            MAX_TABLE_ROWS,
+            df.height()
+        );
+    }
+}
+
+/// The nodes shown in the tree: the largest first, each after the folder containing it,
+/// until `MAX_TREE_NODES` are picked. Small folders and files end up in a summary line instead.
+fn shown_nodes(tree: &FileTree) -> HashSet<usize> {
+    let mut shown = HashSet::new();
+    let mut queue = BinaryHeap::from([(tree.node(tree.root()).size, Reverse(tree.root()))]);
+
+    while let Some((_, Reverse(index))) = queue.pop() {
+        if shown.len() >= MAX_TREE_NODES {
+            break;
+        }
+        shown.insert(index);
+        for child in &tree.node(index).children {
+            queue.push((tree.node(*child).size, Reverse(*child)));
+        }
+    }
+
+    shown
+}
+
+/// Writes a folder as a collapsible `<details>` element, largest children first.
+/// Children that aren't `shown` are summed up in one line.
+fn write_tree_node(
+    html: &mut String,
+    tree: &FileTree,
+    index: usize,
+    depth: usize,
+    shown: &HashSet<usize>,
+) {
+    let node = tree.node(index);
+
+    if !node.is_folder {
+        let _ = writeln!(
+            html,
+            "<div class=\"file\"><span class=\"size\">{}</span>{}</div>",
+            format_size(node.size),
+            escape_html(&node.name)
+        );
+        return;
+    }
+
+    let open = if depth == 0 { " open" } else { "" };
+    let _ = writeln!(
+        html,
+        "<details{}><summary><span class=\"size\">{}</span>{}/ <span class=\"note\">({} files)</span></summary>",
+        open,
+        format_size(node.size),
+        escape_html(&node.name),
+        node.file_count
+    );
+
+    let (mut hidden_count, mut hidden_size) = (0, 0);
+    for child in tree.sorted_children(index, SortOrder::Size) {
+        if shown.contains(&child) {
+            write_tree_node(html, tree, child, depth + 1, shown);
+        } else {
+            hidden_count += 1;
+            hidden_size += tree.node(child).size;
+        }
+    }
+    if hidden_count > 0 {
+        let _ = writeln!(
+            html,
+            "<div class=\"file note\"><span class=\"size\">{}</span>{} smaller entries</div>",
+            format_size(hidden_size),
+            hidden_count
+        );
+    }
+
+    html.push_str("</details>\n");
+}
+
+/// Builds a single, offline HTML report: a table of totals, one table per analysis result,
+/// and a collapsible folder tree. Styling is inlined, so nothing is loaded from the internet.
+pub fn html_report(
+    totals: &[(&str, String)],
+    tables: &[(&str, &DataFrame)],
+    tree: &FileTree,
+) -> String {
+    let root = tree.node(tree.root());
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Folder analysis: {}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>Folder analysis: {}</h1>\n",
+        escape_html(&root.path),
+        STYLE,
+        escape_html(&root.path)
+    );
+
+    html.push_str("<h2>Totals</h2>\n<table>\n");
+    for (name, value) in totals {
+        let _ = writeln!(
+            html,
+            "<tr><th>{}</th><td class=\"number\">{}</td></tr>",
+            escape_html(name),
+            escape_html(value)
+        );
+    }
+    html.push_str("</table>\n");
+
+    for (title, df) in tables {
+        let _ = writeln!(html, "<h2>{}</h2>", escape_html(title));
+        write_table(&mut html, df);
+    }
+
+    html.push_str("<h2>Folder tree</h2>\n");
+    write_tree_node(&mut html, tree, tree.root(), 0, &shown_nodes(tree));
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Saves the HTML report as `report.html` in the analysis folder.
+pub fn save_html_report(
+    totals: &[(&str, String)],
+    tables: &[(&str, &DataFrame)],
+    tree: &FileTree,
+    analysis_folder_path: &Path,
+) -> Result<()> {
+    let report_path = analysis_folder_path.join("report.html");
+    info!("Saving report: {:?}", report_path);
+
+    create_dir_all(analysis_folder_path).map_err(|e| Error::io(analysis_folder_path, e))?;
+    std::fs::write(&report_path, html_report(totals, tables, tree))
+        .map_err(|e| Error::io(&report_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::folder_index::folder_index;
+
+    #[test]
+    fn tree_shows_the_largest_entries_up_to_the_budget() {
+        let count = MAX_TREE_NODES + 100;
+        let names: Vec<String> = (0..count).map(|i| format!("{}.txt", i)).collect();
+        let df = df!(
+            "path" => names.iter().map(|name| format!("/data/{}", name)).collect::<Vec<_>>(),
+            "parents" => vec!["/data"; count],
+            "name" => &names,
+            "extension" => vec![Some("txt"); count],
+            "size" => (0..count as u64).map(Some).collect::<Vec<_>>(),
+            "allocated_size" => vec![Some(0u64); count],
+            "modified" => vec![Some(0i64); count],
+            "is_folder" => vec![false; count],
+            "hash" => vec![None::<&str>; count],
+            "file_type" => vec!["file"; count],
+            "link_target" => vec![None::<&str>; count]
+        )
+        .unwrap();
+        let tree = FileTree::from_df(&df, &folder_index(&df).unwrap()).unwrap();
+
+        let html = html_report(&[], &[], &tree);
+        // The root takes up one of the nodes, the 101 smallest files are summed up.
+        assert_eq!(
+            html.matches("<div class=\"file\">").count(),
+            MAX_TREE_NODES - 1
+        );
+        assert!(html.contains(&format!(
+            "<span class=\"size\">{}</span>101 smaller entries",
+            format_size((0..101).sum())
+        )));
+        assert!(html.contains(">5099.txt<"));
+        assert!(!html.contains(">100.txt<"));
+    }
+}
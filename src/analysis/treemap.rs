@@ -0,0 +1,324 @@
+use std::fmt::Write as _;
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use crate::browse::file_tree::{FileTree, SortOrder};
+use crate::error::{Error, Result};
+
+pub const TREEMAP_WIDTH: u32 = 1600;
+pub const TREEMAP_HEIGHT: u32 = 1000;
+
+/// Space between a folder's outline and its contents, so nested folders stay visible.
+const FOLDER_PADDING: f64 = 2.0;
+
+/// Tiles smaller than this (in pixels) are not drawn, which keeps the SVG at a reasonable size.
+const MIN_TILE_AREA: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+impl Rect {
+    fn area(&self) -> f64 {
+        self.w * self.h
+    }
+
+    fn inset(&self, padding: f64) -> Rect {
+        Rect {
+            x: self.x + padding,
+            y: self.y + padding,
+            w: (self.w - 2.0 * padding).max(0.0),
+            h: (self.h - 2.0 * padding).max(0.0),
+        }
+    }
+}
+
+/// A file or folder placed on the treemap.
+#[derive(Debug)]
+struct Tile {
+    rect: Rect,
+    node: usize,
+}
+
+/// Worst aspect ratio in a row of areas laid out along a side, as in the squarified treemap paper.
+fn worst_ratio(row: &[f64], row_sum: f64, side: f64) -> f64 {
+    let max = row.iter().cloned().fold(f64::MIN, f64::max);
+    let min = row.iter().cloned().fold(f64::MAX, f64::min);
+    let (sum_squared, side_squared) = (row_sum * row_sum, side * side);
+
+    f64::max(
+        side_squared * max / sum_squared,
+        sum_squared / (side_squared * min),
+    )
+}
+
+/// Squarified layout (Bruls, Huizing and van Wijk): splits `rect` into one rectangle per value,
+/// keeping them as close to square as possible. Values must be positive and sorted largest first.
+fn squarify(values: &[f64], rect: Rect) -> Vec<Rect> {
+    let total: f64 = values.iter().sum();
+    if total <= 0.0 || rect.area() <= 0.0 {
+        return Vec::new();
+    }
+
+    let areas: Vec<f64> = values.iter().map(|v| v * rect.area() / total).collect();
+    let mut rects = Vec::with_capacity(areas.len());
+    let mut remaining = rect;
+    let mut start = 0;
+
+    while start < areas.len() {
+        // Rows are laid out along the shorter side of the remaining space.
+        let side = remaining.w.min(remaining.h);
+        let mut end = start + 1;
+        let mut row_sum = areas[start];
+        let mut ratio = worst_ratio(&areas[start..end], row_sum, side);
+
+        while end < areas.len() {
+            let next_sum = row_sum + areas[end];
+            let next_ratio = worst_ratio(&areas[start..=end], next_sum, side);
+            if next_ratio > ratio {
+                break;
+            }
+            ratio = next_ratio;
+            row_sum = next_sum;
+            end += 1;
+        }
+
+        if remaining.w >= remaining.h {
+            let width = row_sum / remaining.h;
+            let mut y = remaining.y;
+            for area in &areas[start..end] {
+                let h = area / width;
+                rects.push(Rect {
+                    x: remaining.x,
+                    y,
+                    w: width,
+                    h,
+                });
+                y += h;
+            }
+            remaining.x += width;
+            remaining.w -= width;
+        } else {
+            let height = row_sum / remaining.w;
+            let mut x = remaining.x;
+            for area in &areas[start..end] {
+                let w = area / height;
+                rects.push(Rect {
+                    x,
+                    y: remaining.y,
+                    w,
+                    h: height,
+                });
+                x += w;
+            }
+            remaining.y += height;
+            remaining.h -= height;
+        }
+
+        start = end;
+    }
+
+    rects
+}
+
+/// Lays out a node and everything below it, parents before children.
+fn layout(tree: &FileTree, node: usize, rect: Rect, tiles: &mut Vec<Tile>) {
+    if rect.area() < MIN_TILE_AREA {
+        return;
+    }
+    tiles.push(Tile { rect, node });
+
+    if !tree.node(node).is_folder {
+        return;
+    }
+
+    let children: Vec<usize> = tree
+        .sorted_children(node, SortOrder::Size)
+        .into_iter()
+        .filter(|child| tree.node(*child).size > 0)
+        .collect();
+    let sizes: Vec<f64> = children
+        .iter()
+        .map(|child| tree.node(*child).size as f64)
+        .collect();
+
+    for (child, child_rect) in children
+        .into_iter()
+        .zip(squarify(&sizes, rect.inset(FOLDER_PADDING)))
+    {
+        layout(tree, child, child_rect, tiles);
+    }
+}
+
+/// Converts a hue (0-360) with fixed saturation and lightness to RGB.
+fn hue_to_rgb(hue: f64) -> [u8; 3] {
+    let (saturation, lightness) = (0.55, 0.55);
+    let chroma = (1.0 - (2.0 * lightness - 1.0_f64).abs()) * saturation;
+    let h = hue / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+
+    [r, g, b].map(|c| ((c + m) * 255.0).round() as u8)
+}
+
+/// Each extension gets a stable color; files without an extension are gray.
+fn extension_color(extension: Option<&str>) -> [u8; 3] {
+    match extension {
+        Some(extension) if !extension.is_empty() => {
+            // FNV-1a, so the same extension gets the same color on every run.
+            let hash = extension
+                .to_lowercase()
+                .bytes()
+                .fold(0xcbf29ce484222325_u64, |hash, byte| {
+                    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+                });
+            hue_to_rgb((hash % 360) as f64)
+        }
+        _ => [160, 160, 160],
+    }
+}
+
+const FOLDER_OUTLINE: [u8; 3] = [40, 40, 40];
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_svg(tree: &FileTree, tiles: &[Tile], width: u32, height: u32) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n"
+    );
+
+    for tile in tiles {
+        let node = tree.node(tile.node);
+        let (fill, stroke) = if node.is_folder {
+            ("none".to_string(), "#282828")
+        } else {
+            let [r, g, b] = extension_color(node.extension.as_deref());
+            (format!("#{r:02x}{g:02x}{b:02x}"), "#00000040")
+        };
+
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"0.5\"><title>{} ({} bytes)</title></rect>",
+            tile.rect.x,
+            tile.rect.y,
+            tile.rect.w,
+            tile.rect.h,
+            fill,
+            stroke,
+            escape_xml(&node.path),
+            node.size
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Rasterizes the tiles: files are filled with their extension color, folders get an outline.
+fn to_pixels(tree: &FileTree, tiles: &[Tile], width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = vec![255_u8; (width * height * 3) as usize];
+
+    let mut set_pixel = |x: u32, y: u32, color: [u8; 3]| {
+        if x < width && y < height {
+            let offset = ((y * width + x) * 3) as usize;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    };
+
+    for tile in tiles {
+        let node = tree.node(tile.node);
+        let (x0, y0) = (tile.rect.x.round() as u32, tile.rect.y.round() as u32);
+        let x1 = (tile.rect.x + tile.rect.w).round() as u32;
+        let y1 = (tile.rect.y + tile.rect.h).round() as u32;
+        if x1 <= x0 || y1 <= y0 {
+            continue;
+        }
+
+        if node.is_folder {
+            for x in x0..x1 {
+                set_pixel(x, y0, FOLDER_OUTLINE);
+                set_pixel(x, y1 - 1, FOLDER_OUTLINE);
+            }
+            for y in y0..y1 {
+                set_pixel(x0, y, FOLDER_OUTLINE);
+                set_pixel(x1 - 1, y, FOLDER_OUTLINE);
+            }
+        } else {
+            let color = extension_color(node.extension.as_deref());
+            // Darkening the bottom/right edge, so neighbouring files of the same type can be told apart.
+            let edge = color.map(|c| c / 2);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let is_edge = (x == x1 - 1 || y == y1 - 1) && x1 - x0 > 2 && y1 - y0 > 2;
+                    set_pixel(x, y, if is_edge { edge } else { color });
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Renders a squarified treemap of the cumulative folder sizes, colored by extension,
+/// to `treemap.svg` and `treemap.png` in the analysis folder.
+pub fn save_treemap(
+    tree: &FileTree,
+    analysis_folder_path: &Path,
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let mut tiles = Vec::new();
+    layout(
+        tree,
+        tree.root(),
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            w: width as f64,
+            h: height as f64,
+        },
+        &mut tiles,
+    );
+
+    create_dir_all(analysis_folder_path).map_err(|e| Error::io(analysis_folder_path, e))?;
+
+    let svg_path = analysis_folder_path.join("treemap.svg");
+    info!("Saving treemap: {:?}", svg_path);
+    std::fs::write(&svg_path, to_svg(tree, &tiles, width, height))
+        .map_err(|e| Error::io(&svg_path, e))?;
+
+    let png_path = analysis_folder_path.join("treemap.png");
+    info!("Saving treemap: {:?}", png_path);
+    let file = File::create(&png_path).map_err(|e| Error::io(&png_path, e))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(&to_pixels(tree, &tiles, width, height))?;
+
+    Ok(())
+}
@@ -0,0 +1,2 @@
+pub mod browser;
+pub mod file_tree;
@@ -0,0 +1,243 @@
+use chrono::DateTime;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::browse::file_tree::{ExtensionTotals, FileTree, SortOrder};
+use crate::utils::formatting::format_size;
+
+const BAR_WIDTH: usize = 10;
+
+fn format_modified(modified: Option<i64>) -> String {
+    modified
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map_or_else(
+            || "-".to_string(),
+            |modified| modified.format("%Y-%m-%d").to_string(),
+        )
+}
+
+/// Navigation state of the browser.
+struct Browser {
+    tree: FileTree,
+    current: usize,
+    sort_order: SortOrder,
+    entries: Vec<usize>,
+    list_state: ListState,
+    /// Selected row in each parent folder, restored when going back up.
+    history: Vec<usize>,
+    /// Extension breakdowns of folders already shown, as each one walks the whole subtree.
+    breakdowns: HashMap<usize, Vec<ExtensionTotals>>,
+}
+
+impl Browser {
+    fn new(tree: FileTree) -> Self {
+        let mut browser = Browser {
+            current: tree.root(),
+            tree,
+            sort_order: SortOrder::default(),
+            entries: Vec::new(),
+            list_state: ListState::default(),
+            history: Vec::new(),
+            breakdowns: HashMap::new(),
+        };
+        browser.refresh(0);
+        browser
+    }
+
+    /// Re-sorts the entries of the current folder and selects a row.
+    fn refresh(&mut self, selected: usize) {
+        self.entries = self.tree.sorted_children(self.current, self.sort_order);
+        self.list_state
+            .select((!self.entries.is_empty()).then(|| selected.min(self.entries.len() - 1)));
+    }
+
+    fn selected_entry(&self) -> Option<usize> {
+        self.list_state
+            .selected()
+            .and_then(|row| self.entries.get(row).copied())
+    }
+
+    fn enter(&mut self) {
+        if let Some(entry) = self.selected_entry() {
+            if self.tree.node(entry).is_folder {
+                self.history.push(self.list_state.selected().unwrap_or(0));
+                self.current = entry;
+                self.refresh(0);
+            }
+        }
+    }
+
+    fn leave(&mut self) {
+        if let Some(parent) = self.tree.node(self.current).parent {
+            self.current = parent;
+            let selected = self.history.pop().unwrap_or(0);
+            self.refresh(selected);
+        }
+    }
+
+    fn sort_by(&mut self, sort_order: SortOrder) {
+        // Keeping the same entry selected after sorting.
+        let selected = self.selected_entry();
+        self.sort_order = sort_order;
+        self.refresh(0);
+
+        if let Some(row) = selected.and_then(|entry| self.entries.iter().position(|e| *e == entry))
+        {
+            self.list_state.select(Some(row));
+        }
+    }
+
+    /// Handles a key press, returning false when the browser should close.
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.list_state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.list_state.select_previous(),
+            KeyCode::PageDown => self.list_state.scroll_down_by(20),
+            KeyCode::PageUp => self.list_state.scroll_up_by(20),
+            KeyCode::Home => self.list_state.select_first(),
+            KeyCode::End => self.list_state.select_last(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.enter(),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => self.leave(),
+            KeyCode::Char('s') => self.sort_by(SortOrder::Size),
+            KeyCode::Char('c') => self.sort_by(SortOrder::Count),
+            KeyCode::Char('m') => self.sort_by(SortOrder::Modified),
+            KeyCode::Char('n') => self.sort_by(SortOrder::Name),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header_area, body_area, footer_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, extension_area] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(body_area);
+
+        let current = self.tree.node(self.current);
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                "{}  {}  {} files",
+                current.path,
+                format_size(current.size),
+                current.file_count
+            ))
+            .bold(),
+            header_area,
+        );
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let node = self.tree.node(*entry);
+                let filled = if current.size > 0 {
+                    ((node.size as f64 / current.size as f64) * BAR_WIDTH as f64).round() as usize
+                } else {
+                    0
+                };
+                let bar = format!("{}{}", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled));
+                let name = if node.is_folder {
+                    format!("{}/", node.name)
+                } else {
+                    node.name.clone()
+                };
+
+                ListItem::new(Line::from(format!(
+                    "{:>10} [{}] {:>8} {:>10}  {}",
+                    format_size(node.size),
+                    bar,
+                    node.file_count,
+                    format_modified(node.modified),
+                    name
+                )))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" sorted by {} ", self.sort_order.as_str())))
+            .highlight_style(Style::new().reversed())
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        // Breakdown of the selected folder, or of the current folder when a file is selected.
+        let breakdown_folder = self
+            .selected_entry()
+            .filter(|entry| self.tree.node(*entry).is_folder)
+            .unwrap_or(self.current);
+
+        let rows: Vec<Row> = self
+            .breakdowns
+            .entry(breakdown_folder)
+            .or_insert_with(|| self.tree.extension_breakdown(breakdown_folder))
+            .iter()
+            .map(|totals| {
+                Row::new(vec![
+                    if totals.extension.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        totals.extension.clone()
+                    },
+                    format_size(totals.size),
+                    totals.file_count.to_string(),
+                ])
+            })
+            .collect();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(8),
+                Constraint::Length(10),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(vec!["extension", "size", "files"]).bold())
+        .block(Block::bordered().title(format!(
+            " extensions in {} ",
+            self.tree.node(breakdown_folder).name
+        )));
+        frame.render_widget(table, extension_area);
+
+        frame.render_widget(
+            Paragraph::new(
+                "q quit  ↑/↓ move  enter/→ open  ←/backspace up  sort: s size, c count, m modified, n name",
+            ),
+            footer_area,
+        );
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Opens an interactive ncdu-style browser over the tree, until the user quits.
+pub fn run_browser(tree: FileTree) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Browser::new(tree).run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
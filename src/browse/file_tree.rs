@@ -0,0 +1,282 @@
+use polars::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::indexing::folder_index::index_root;
+
+/// A file or folder in the tree. Folder sizes, counts and modification times cover everything below them.
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub name: String,
+    pub path: String,
+    pub is_folder: bool,
+    pub extension: Option<String>,
+    pub size: u64,
+    pub file_count: u64,
+    pub modified: Option<i64>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// How the children of a folder are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Size,
+    Count,
+    Modified,
+    Name,
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Size => "size",
+            SortOrder::Count => "count",
+            SortOrder::Modified => "modified",
+            SortOrder::Name => "name",
+        }
+    }
+}
+
+/// Total size and file count for a single extension.
+#[derive(Debug, Clone)]
+pub struct ExtensionTotals {
+    pub extension: String,
+    pub size: u64,
+    pub file_count: u64,
+}
+
+/// In-memory tree of an index, built from the cached DataFrame without touching the disk.
+#[derive(Debug)]
+pub struct FileTree {
+    nodes: Vec<TreeNode>,
+}
+
+impl FileTree {
+    /// Builds the tree of an index, with the folder totals of its `folder_index`, so the tree
+    /// shows the same sizes and counts as the folders table. Entries outside of the folder index,
+    /// whose parent folder isn't indexed, are left out as well.
+    pub fn from_df(df: &DataFrame, folders: &DataFrame) -> Result<Self, PolarsError> {
+        let root = index_root(df)?;
+
+        let folder_paths = folders.column("path")?.str()?;
+        let total_sizes = folders.column("total_size")?.u64()?;
+        let file_counts = folders.column("file_count")?.u64()?;
+        let folder_totals: HashMap<&str, (u64, u64)> = folder_paths
+            .into_iter()
+            .zip(total_sizes.into_iter().zip(file_counts))
+            .filter_map(|(path, (size, file_count))| {
+                Some((path?, (size.unwrap_or(0), file_count.unwrap_or(0))))
+            })
+            .collect();
+        let totals = |path: &str| folder_totals.get(path).copied().unwrap_or_default();
+
+        let paths = df.column("path")?.str()?;
+        let parents = df.column("parents")?.str()?;
+        let names = df.column("name")?.str()?;
+        let extensions = df.column("extension")?.str()?;
+        let sizes = df.column("size")?.u64()?;
+        let modified = df.column("modified")?.i64()?;
+        let is_folders = df.column("is_folder")?.bool()?;
+
+        // The root itself is not part of the index.
+        let (size, file_count) = totals(&root);
+        let mut nodes = vec![TreeNode {
+            name: root.clone(),
+            path: root.clone(),
+            is_folder: true,
+            extension: None,
+            size,
+            file_count,
+            modified: None,
+            parent: None,
+            children: Vec::new(),
+        }];
+        let mut parent_paths = vec![None];
+
+        for ((((((path, parent), name), extension), size), modified), is_folder) in paths
+            .into_iter()
+            .zip(parents)
+            .zip(names)
+            .zip(extensions)
+            .zip(sizes)
+            .zip(modified)
+            .zip(is_folders)
+        {
+            let Some(path) = path else {
+                continue;
+            };
+            let is_folder = is_folder.unwrap_or(false);
+            // Folder sizes are the sum of their contents, not the size of the folder entry.
+            let (size, file_count) = if is_folder {
+                totals(path)
+            } else {
+                (size.unwrap_or(0), 1)
+            };
+
+            nodes.push(TreeNode {
+                name: name.unwrap_or(path).to_string(),
+                path: path.to_string(),
+                is_folder,
+                extension: extension.map(str::to_string),
+                size,
+                file_count,
+                modified,
+                parent: None,
+                children: Vec::new(),
+            });
+            parent_paths.push(parent);
+        }
+
+        let folder_nodes: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_folder)
+            .map(|(index, node)| (node.path.as_str(), index))
+            .collect();
+
+        let links: Vec<(usize, usize)> = parent_paths
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter_map(|(index, parent)| {
+                let parent_index = folder_nodes.get(parent.as_deref()?).copied()?;
+                (parent_index != index).then_some((index, parent_index))
+            })
+            .collect();
+
+        for (index, parent_index) in links {
+            nodes[index].parent = Some(parent_index);
+            nodes[parent_index].children.push(index);
+        }
+
+        let mut tree = FileTree { nodes };
+        tree.latest_modified();
+
+        Ok(tree)
+    }
+
+    /// Gives every folder the latest modification time below it, deepest nodes first.
+    /// The folder index has no modification times.
+    fn latest_modified(&mut self) {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![self.root()];
+
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(self.nodes[index].children.iter().copied());
+        }
+
+        for index in order.into_iter().rev() {
+            let Some(parent) = self.nodes[index].parent else {
+                continue;
+            };
+            let modified = self.nodes[index].modified;
+            let parent = &mut self.nodes[parent];
+            parent.modified = parent.modified.max(modified);
+        }
+    }
+
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    pub fn node(&self, index: usize) -> &TreeNode {
+        &self.nodes[index]
+    }
+
+    /// Children of a folder: largest, most files or most recently modified first, or by name.
+    pub fn sorted_children(&self, index: usize, sort_order: SortOrder) -> Vec<usize> {
+        let mut children = self.nodes[index].children.clone();
+
+        children.sort_by(|a, b| {
+            let (a, b) = (&self.nodes[*a], &self.nodes[*b]);
+            match sort_order {
+                SortOrder::Size => b.size.cmp(&a.size),
+                SortOrder::Count => b.file_count.cmp(&a.file_count),
+                SortOrder::Modified => b.modified.cmp(&a.modified),
+                SortOrder::Name => a.name.cmp(&b.name),
+            }
+            .then_with(|| a.name.cmp(&b.name))
+        });
+
+        children
+    }
+
+    /// Size and file count per extension for everything below a folder, largest first.
+    pub fn extension_breakdown(&self, index: usize) -> Vec<ExtensionTotals> {
+        let mut totals: HashMap<&str, (u64, u64)> = HashMap::new();
+        let mut stack = vec![index];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            if node.is_folder {
+                stack.extend(node.children.iter().copied());
+            } else {
+                let extension = node.extension.as_deref().unwrap_or("");
+                let total = totals.entry(extension).or_default();
+                total.0 += node.size;
+                total.1 += 1;
+            }
+        }
+
+        let mut breakdown: Vec<ExtensionTotals> = totals
+            .into_iter()
+            .map(|(extension, (size, file_count))| ExtensionTotals {
+                extension: extension.to_string(),
+                size,
+                file_count,
+            })
+            .collect();
+
+        breakdown.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| a.extension.cmp(&b.extension))
+        });
+        breakdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::folder_index::folder_index;
+
+    #[test]
+    fn folder_totals_match_the_folder_index() {
+        let df = df!(
+            "path" => ["/data/a", "/data/top.txt", "/data/a/x.txt", "/data/a/link", "/data-old/y.txt"],
+            "parents" => ["/data", "/data", "/data/a", "/data/a", "/data-old"],
+            "name" => ["a", "top.txt", "x.txt", "link", "y.txt"],
+            "extension" => [None, Some("txt"), Some("txt"), None, Some("txt")],
+            "size" => [Some(4096u64), Some(10), Some(20), Some(5), Some(1000)],
+            "allocated_size" => [Some(4096u64), Some(4096), Some(4096), Some(0), Some(4096)],
+            "modified" => [Some(1i64), Some(2), Some(3), Some(4), Some(5)],
+            "is_folder" => [true, false, false, false, false],
+            "hash" => [None::<&str>, None, None, None, None],
+            "file_type" => ["folder", "file", "file", "symlink", "file"],
+            "link_target" => [None, None, None, Some("x.txt"), None]
+        )
+        .unwrap();
+        let folders = folder_index(&df).unwrap();
+        let tree = FileTree::from_df(&df, &folders).unwrap();
+
+        let root = tree.node(tree.root());
+        assert_eq!(
+            (root.size, root.file_count, root.modified),
+            (35, 3, Some(4))
+        );
+        let a = tree.node(tree.sorted_children(tree.root(), SortOrder::Name)[0]);
+        assert_eq!((a.name.as_str(), a.size, a.file_count), ("a", 25, 2));
+
+        // The file outside of the indexed root is in neither.
+        assert_eq!(
+            folders.column("total_size").unwrap().u64().unwrap().get(0),
+            Some(35)
+        );
+        assert_eq!(root.children.len(), 2);
+    }
+}
@@ -0,0 +1,91 @@
+use polars::prelude::PolarsError;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Errors returned by the library.
+/// While indexing, errors for single paths are collected instead, so one unreadable file doesn't end the walk.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file or folder failed.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Building, reading or writing a DataFrame failed.
+    Polars(PolarsError),
+    /// An include or exclude pattern is not a valid glob.
+    Pattern(ignore::Error),
+    /// The worker threads for indexing could not be started.
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// The treemap could not be encoded as PNG.
+    Png(png::EncodingError),
+    /// A cache can't be used for what was asked, e.g. it was indexed without hashes.
+    InvalidCache(String),
+    /// A checksum manifest can't be read.
+    Manifest(String),
+}
+
+/// Result with the library's error type.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// An I/O error, together with the path it happened on.
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{:?}: {}", path, source),
+            Error::Polars(e) => write!(f, "{}", e),
+            Error::Pattern(e) => write!(f, "invalid pattern: {}", e),
+            Error::ThreadPool(e) => write!(f, "failed to start the worker threads: {}", e),
+            Error::Png(e) => write!(f, "failed to encode PNG: {}", e),
+            Error::InvalidCache(message) => write!(f, "{}", message),
+            Error::Manifest(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Polars(e) => Some(e),
+            Error::Pattern(e) => Some(e),
+            Error::ThreadPool(e) => Some(e),
+            Error::Png(e) => Some(e),
+            Error::InvalidCache(_) | Error::Manifest(_) => None,
+        }
+    }
+}
+
+impl From<PolarsError> for Error {
+    fn from(e: PolarsError) -> Self {
+        Error::Polars(e)
+    }
+}
+
+impl From<ignore::Error> for Error {
+    fn from(e: ignore::Error) -> Self {
+        Error::Pattern(e)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(e: rayon::ThreadPoolBuildError) -> Self {
+        Error::ThreadPool(e)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Error::Png(e)
+    }
+}
@@ -1,2 +1,8 @@
+pub mod cache_metadata;
+pub mod folder_index;
+pub mod index_cache;
 pub mod index_creation;
+pub mod index_options;
 pub mod index_processing;
+pub mod index_writer;
+pub mod path_filter;
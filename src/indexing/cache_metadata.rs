@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+
+use crate::indexing::index_options::IndexOptions;
+use crate::indexing::path_filter::PathFilter;
+use crate::utils::hashing::{HashAlgorithm, QuickHash};
+
+/// Prefix of the parquet key-value metadata keys written by this tool.
+pub const METADATA_KEY_PREFIX: &str = "rust_file_index.";
+
+/// Describes how and when a cache was created, saved as parquet key-value metadata.
+#[derive(Debug, Clone)]
+pub struct CacheMetadata {
+    pub root: String,
+    pub hostname: String,
+    pub tool_version: String,
+    pub get_metadata: bool,
+    pub get_hash: bool,
+    pub hash_duplicates: bool,
+    pub hash_algorithm: HashAlgorithm,
+    pub quick_hash: Option<QuickHash>,
+    pub incremental: bool,
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub ignore_files: bool,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub entry_count: usize,
+    pub file_count: usize,
+    pub folder_count: usize,
+}
+
+impl CacheMetadata {
+    /// Metadata for an index of `root` that ran from `started` until now.
+    pub fn new(
+        root: &Path,
+        options: &IndexOptions,
+        started: DateTime<Utc>,
+        entry_count: usize,
+        folder_count: usize,
+    ) -> Self {
+        CacheMetadata {
+            root: root
+                .canonicalize()
+                .unwrap_or_else(|_| root.to_path_buf())
+                .to_string_lossy()
+                .into_owned(),
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            get_metadata: options.get_metadata,
+            get_hash: options.get_hash,
+            hash_duplicates: options.hash_duplicates,
+            hash_algorithm: options.hash_algorithm,
+            quick_hash: options.quick_hash,
+            incremental: options.incremental,
+            follow_symlinks: options.follow_symlinks,
+            one_file_system: options.one_file_system,
+            include: options.filter.include().to_vec(),
+            exclude: options.filter.exclude().to_vec(),
+            ignore_files: options.filter.uses_ignore_files(),
+            started,
+            finished: Utc::now(),
+            entry_count,
+            file_count: entry_count - folder_count,
+            folder_count,
+        }
+    }
+
+    /// Key-value pairs as stored in the parquet footer.
+    pub fn key_values(&self) -> Vec<(String, String)> {
+        [
+            ("root", self.root.clone()),
+            ("hostname", self.hostname.clone()),
+            ("tool_version", self.tool_version.clone()),
+            ("metadata", self.get_metadata.to_string()),
+            ("hash", self.get_hash.to_string()),
+            ("hash_duplicates", self.hash_duplicates.to_string()),
+            ("hash_algorithm", self.hash_algorithm.to_string()),
+            (
+                "quick_hash",
+                self.quick_hash
+                    .map_or("false".to_string(), |quick_hash| quick_hash.to_string()),
+            ),
+            ("incremental", self.incremental.to_string()),
+            ("follow_symlinks", self.follow_symlinks.to_string()),
+            ("one_file_system", self.one_file_system.to_string()),
+            (
+                "include",
+                serde_json::to_string(&self.include).unwrap_or_default(),
+            ),
+            (
+                "exclude",
+                serde_json::to_string(&self.exclude).unwrap_or_default(),
+            ),
+            ("ignore_files", self.ignore_files.to_string()),
+            ("started", self.started.to_rfc3339()),
+            ("finished", self.finished.to_rfc3339()),
+            ("entries", self.entry_count.to_string()),
+            ("files", self.file_count.to_string()),
+            ("folders", self.folder_count.to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (format!("{}{}", METADATA_KEY_PREFIX, key), value))
+        .collect()
+    }
+}
+
+/// Value of a key in the metadata of a cache, as loaded by `load_cache_metadata`.
+fn value<'a>(key_values: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    key_values
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn flag(key_values: &[(String, String)], key: &str) -> bool {
+    value(key_values, key) == Some("true")
+}
+
+fn patterns(key_values: &[(String, String)], key: &str) -> Vec<String> {
+    value(key_values, key)
+        .and_then(|patterns| serde_json::from_str(patterns).ok())
+        .unwrap_or_default()
+}
+
+/// The folder a cache was created from, as recorded in its metadata. Unlike the paths in the cache,
+/// which are stored as given on the command line, this is canonical, so it doesn't depend on the
+/// current folder. Caches from older versions have none.
+pub fn recorded_root(key_values: &[(String, String)]) -> Option<PathBuf> {
+    value(key_values, "root").map(PathBuf::from)
+}
+
+/// When the indexing of the cache started, if recorded.
+pub fn recorded_start(key_values: &[(String, String)]) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value(key_values, "started")?)
+        .ok()
+        .map(|started| started.with_timezone(&Utc))
+}
+
+/// Whether the cache was walked the way `options` walk: with the same include and exclude patterns,
+/// ignore files and symlink handling, so it holds the same entries for every folder.
+/// Caches from before the filter was recorded never match.
+pub fn same_walk(key_values: &[(String, String)], options: &IndexOptions) -> bool {
+    value(key_values, "include").is_some()
+        && patterns(key_values, "include") == options.filter.include()
+        && patterns(key_values, "exclude") == options.filter.exclude()
+        && flag(key_values, "ignore_files") == options.filter.uses_ignore_files()
+        && flag(key_values, "follow_symlinks") == options.follow_symlinks
+}
+
+/// Options for a walk that visits the same paths as the index described by the metadata of its cache,
+/// without metadata or hashes. Caches from before the filter was recorded are walked without one.
+pub fn walk_options(
+    key_values: &[(String, String)],
+    root: &Path,
+) -> Result<IndexOptions, ignore::Error> {
+    Ok(IndexOptions {
+        follow_symlinks: flag(key_values, "follow_symlinks"),
+        one_file_system: flag(key_values, "one_file_system"),
+        filter: PathFilter::new(
+            root,
+            &patterns(key_values, "include"),
+            &patterns(key_values, "exclude"),
+            flag(key_values, "ignore_files"),
+        )?,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,452 @@
+use polars::prelude::*;
+use ring::digest::{Context, SHA256};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::path_data::FileType;
+
+/// Cumulative totals for a folder, covering all of its subfolders.
+#[derive(Debug, Default, Clone)]
+struct FolderTotals {
+    total_size: u64,
+    allocated_size: u64,
+    file_count: u64,
+    subfolder_count: u64,
+}
+
+/// The indexed root is the shortest parent path in the index.
+pub fn index_root(df: &DataFrame) -> Result<String, PolarsError> {
+    let root = df
+        .column("parents")?
+        .str()?
+        .into_iter()
+        .flatten()
+        .min_by_key(|parent| parent.len())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(root)
+}
+
+/// Merkle state of a folder while its entries are added.
+#[derive(Default)]
+struct FolderMerkle {
+    /// Files, links and other entries, hashed as soon as the folder's listing is added.
+    entries: Option<Context>,
+    /// Subfolders by name, hashed once their own hashes are known.
+    subfolders: Vec<(String, usize)>,
+    /// Whether a file in the folder has no hash.
+    unhashed: bool,
+}
+
+/// Builds the folder-level index one batch of index entries at a time, so only the folders are
+/// held in memory, never every entry. The entries of a folder have to be added one after another,
+/// as the walk lists them.
+///
+/// Sizes only include files (and links), not the size of the folder entries themselves.
+/// Every folder counts all files below it, including those in subfolders.
+///
+/// Each folder also gets a Merkle hash: the SHA256 of its files, links and other entries sorted by
+/// name, followed by its subfolders sorted by name. Each entry is its kind, name and content hash,
+/// each ended by a NUL byte. Files use their file hash, links their target, and folders their own
+/// Merkle hash, so equal hashes mean equal trees, regardless of the order of indexing.
+/// A folder containing a file without a hash, directly or in a subfolder, gets no hash.
+pub struct FolderIndexBuilder {
+    root: PathBuf,
+    /// Folders in the order they were found, so every folder comes after its parent.
+    folder_paths: Vec<PathBuf>,
+    folders: HashMap<PathBuf, usize>,
+    /// Totals of the entries directly in each folder, until `finish` adds up the subfolders.
+    totals: Vec<FolderTotals>,
+    merkle: Vec<FolderMerkle>,
+    /// The folder whose entries are being added, with its non-folder Merkle entries so far.
+    open: Option<(usize, Vec<(String, String)>)>,
+}
+
+impl FolderIndexBuilder {
+    pub fn new(root: &Path) -> Self {
+        // The root itself is not part of the index, so it is added separately.
+        let mut builder = FolderIndexBuilder {
+            root: root.to_path_buf(),
+            folder_paths: Vec::new(),
+            folders: HashMap::new(),
+            totals: Vec::new(),
+            merkle: Vec::new(),
+            open: None,
+        };
+        builder.add_folder(root);
+        builder
+    }
+
+    fn add_folder(&mut self, path: &Path) -> usize {
+        let index = self.folder_paths.len();
+        self.folder_paths.push(path.to_path_buf());
+        self.folders.insert(path.to_path_buf(), index);
+        self.totals.push(FolderTotals::default());
+        self.merkle.push(FolderMerkle::default());
+        index
+    }
+
+    /// Adds a batch of index entries.
+    pub fn add(&mut self, df: &DataFrame) -> PolarsResult<()> {
+        let paths = df.column("path")?.str()?;
+        let names = df.column("name")?.str()?;
+        let sizes = df.column("size")?.u64()?;
+        let allocated_sizes = df.column("allocated_size")?.u64()?;
+        let is_folders = df.column("is_folder")?.bool()?;
+        let hashes = df.column("hash")?.str()?;
+        let file_types = df.column("file_type")?.str()?;
+        let link_targets = df.column("link_target")?.str()?;
+
+        for row in 0..df.height() {
+            let (Some(path), Some(name)) = (paths.get(row), names.get(row)) else {
+                continue;
+            };
+            let path = Path::new(path);
+            let Some(parent) = path.parent().and_then(|parent| self.folders.get(parent)) else {
+                continue;
+            };
+            let parent = *parent;
+            self.open_folder(parent)?;
+
+            if is_folders.get(row).unwrap_or(false) {
+                self.totals[parent].subfolder_count += 1;
+                let folder = self.add_folder(path);
+                self.merkle[parent]
+                    .subfolders
+                    .push((name.to_string(), folder));
+                continue;
+            }
+
+            let totals = &mut self.totals[parent];
+            totals.total_size += sizes.get(row).unwrap_or(0);
+            totals.allocated_size += allocated_sizes.get(row).unwrap_or(0);
+            totals.file_count += 1;
+
+            let file_type = file_types.get(row).unwrap_or(FileType::Other.as_str());
+            let entry = match file_type {
+                "file" => match hashes.get(row) {
+                    Some(hash) => format!("file\0{}\0{}\0", name, hash),
+                    None => {
+                        self.merkle[parent].unhashed = true;
+                        continue;
+                    }
+                },
+                "symlink" => format!(
+                    "symlink\0{}\0{}\0",
+                    name,
+                    link_targets.get(row).unwrap_or_default()
+                ),
+                _ => format!("{}\0{}\0\0", file_type, name),
+            };
+            if let Some((_, entries)) = &mut self.open {
+                entries.push((name.to_string(), entry));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts adding the entries of `folder`, hashing the entries of the previous folder.
+    fn open_folder(&mut self, folder: usize) -> PolarsResult<()> {
+        if self.open.as_ref().is_some_and(|(open, _)| *open == folder) {
+            return Ok(());
+        }
+        self.close_folder();
+
+        if self.merkle[folder].entries.is_some() {
+            polars_bail!(
+                ComputeError: "the entries of {:?} are not listed together", self.folder_paths[folder]
+            );
+        }
+        self.open = Some((folder, Vec::new()));
+        Ok(())
+    }
+
+    fn close_folder(&mut self) {
+        if let Some((folder, mut entries)) = self.open.take() {
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut context = Context::new(&SHA256);
+            for (_, entry) in entries {
+                context.update(entry.as_bytes());
+            }
+            self.merkle[folder].entries = Some(context);
+        }
+    }
+
+    /// Merkle hash of every folder. Subfolders come after their parents, so going backwards
+    /// hashes them before the folders containing them.
+    fn merkle_hashes(&mut self) -> Vec<Option<String>> {
+        let mut hashes: Vec<Option<String>> = vec![None; self.folder_paths.len()];
+
+        for index in (0..self.folder_paths.len()).rev() {
+            let merkle = &mut self.merkle[index];
+            if merkle.unhashed {
+                continue;
+            }
+
+            // Folders that were not listed, like those on other filesystems, are empty.
+            let mut context = merkle
+                .entries
+                .take()
+                .unwrap_or_else(|| Context::new(&SHA256));
+            merkle.subfolders.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut complete = true;
+            for (name, subfolder) in &merkle.subfolders {
+                match &hashes[*subfolder] {
+                    Some(hash) => {
+                        context.update(format!("folder\0{}\0{}\0", name, hash).as_bytes())
+                    }
+                    None => {
+                        complete = false;
+                        break;
+                    }
+                }
+            }
+
+            // Names, hashes and link targets can't contain NUL, so no two listings hash the same bytes.
+            if complete {
+                hashes[index] = Some(hex::encode(context.finish().as_ref()));
+            }
+        }
+
+        hashes
+    }
+
+    /// The folder index, with the totals of every folder covering its subfolders.
+    pub fn finish(mut self) -> PolarsResult<DataFrame> {
+        self.close_folder();
+        let merkle = self.merkle_hashes();
+
+        // Subfolders come after their parents, so going backwards adds them up from the bottom.
+        for index in (1..self.folder_paths.len()).rev() {
+            let Some(parent) = self.folder_paths[index]
+                .parent()
+                .and_then(|parent| self.folders.get(parent))
+            else {
+                continue;
+            };
+            let (parents, children) = self.totals.split_at_mut(index);
+            let (parent, child) = (&mut parents[*parent], &children[0]);
+            parent.total_size += child.total_size;
+            parent.allocated_size += child.allocated_size;
+            parent.file_count += child.file_count;
+            parent.subfolder_count += child.subfolder_count;
+        }
+
+        let root = &self.root;
+        let folder_paths = &self.folder_paths;
+        let totals = &self.totals;
+
+        let depth = |path: &PathBuf| {
+            path.strip_prefix(root)
+                .map_or(0, |relative| relative.components().count() as u32)
+        };
+        // Relative paths line up the folders of two caches of different roots, e.g. to compare their hashes.
+        let relative_path = |path: &PathBuf| match path.strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => path.to_string_lossy().into_owned(),
+        };
+
+        DataFrame::new(vec![
+            Series::new(
+                "path",
+                folder_paths
+                    .iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect::<Vec<String>>(),
+            ),
+            Series::new(
+                "parents",
+                folder_paths
+                    .iter()
+                    .map(|path| {
+                        path.parent()
+                            .map(|parent| parent.to_string_lossy().into_owned())
+                    })
+                    .collect::<Vec<Option<String>>>(),
+            ),
+            Series::new(
+                "relative_path",
+                folder_paths
+                    .iter()
+                    .map(relative_path)
+                    .collect::<Vec<String>>(),
+            ),
+            Series::new(
+                "depth",
+                folder_paths.iter().map(depth).collect::<Vec<u32>>(),
+            ),
+            Series::new(
+                "total_size",
+                totals.iter().map(|t| t.total_size).collect::<Vec<u64>>(),
+            ),
+            Series::new(
+                "allocated_size",
+                totals
+                    .iter()
+                    .map(|t| t.allocated_size)
+                    .collect::<Vec<u64>>(),
+            ),
+            Series::new(
+                "file_count",
+                totals.iter().map(|t| t.file_count).collect::<Vec<u64>>(),
+            ),
+            Series::new(
+                "subfolder_count",
+                totals
+                    .iter()
+                    .map(|t| t.subfolder_count)
+                    .collect::<Vec<u64>>(),
+            ),
+            Series::new("merkle_hash", merkle),
+        ])
+    }
+}
+
+/// Folder-level index with cumulative sizes and Merkle hashes of an index held in memory,
+/// see `FolderIndexBuilder`.
+pub fn folder_index(df: &DataFrame) -> Result<DataFrame, PolarsError> {
+    // Entries are listed per folder when indexing, but sorting makes sure of it.
+    let df = df.sort(
+        ["parents"],
+        SortMultipleOptions::default().with_maintain_order(true),
+    )?;
+
+    let mut builder = FolderIndexBuilder::new(Path::new(&index_root(&df)?));
+    builder.add(&df)?;
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small index under `root`, with `a/y.txt` hashed as `y_hash`.
+    fn index(root: &str, y_hash: Option<&str>) -> DataFrame {
+        let entries = [
+            ("a", "", "folder", None, None),
+            ("b", "", "folder", None, None),
+            ("top.txt", "", "file", Some("11"), None),
+            ("x.txt", "a", "file", Some("22"), None),
+            ("y.txt", "a", "file", y_hash, None),
+            ("link", "a", "symlink", None, Some("x.txt")),
+            ("c", "a", "folder", None, None),
+            ("z.txt", "a/c", "file", Some("33"), None),
+            ("w.txt", "b", "file", Some("44"), None),
+        ];
+
+        let parent = |relative: &str| {
+            if relative.is_empty() {
+                root.to_string()
+            } else {
+                format!("{}/{}", root, relative)
+            }
+        };
+
+        df!(
+            "path" => entries.iter().map(|e| format!("{}/{}", parent(e.1), e.0)).collect::<Vec<_>>(),
+            "name" => entries.iter().map(|e| e.0).collect::<Vec<_>>(),
+            "parents" => entries.iter().map(|e| parent(e.1)).collect::<Vec<_>>(),
+            "size" => entries.iter().map(|e| if e.2 == "file" { 10u64 } else { 0 }).collect::<Vec<_>>(),
+            "allocated_size" => entries.iter().map(|e| if e.2 == "file" { 4096u64 } else { 0 }).collect::<Vec<_>>(),
+            "is_folder" => entries.iter().map(|e| e.2 == "folder").collect::<Vec<_>>(),
+            "file_type" => entries.iter().map(|e| e.2).collect::<Vec<_>>(),
+            "hash" => entries.iter().map(|e| e.3).collect::<Vec<_>>(),
+            "link_target" => entries.iter().map(|e| e.4).collect::<Vec<_>>()
+        )
+        .unwrap()
+    }
+
+    /// Merkle hash of each folder by its path relative to the root.
+    fn merkle_hashes(df: &DataFrame) -> HashMap<String, Option<String>> {
+        let folders = folder_index(df).unwrap();
+        let paths = folders.column("relative_path").unwrap().str().unwrap();
+        let hashes = folders.column("merkle_hash").unwrap().str().unwrap();
+
+        paths
+            .into_iter()
+            .zip(hashes)
+            .map(|(path, hash)| (path.unwrap().to_string(), hash.map(str::to_string)))
+            .collect()
+    }
+
+    fn shuffled(df: &DataFrame, order: &[IdxSize]) -> DataFrame {
+        df.take(&IdxCa::from_vec("order", order.to_vec())).unwrap()
+    }
+
+    #[test]
+    fn merkle_hashes_ignore_row_order() {
+        let df = index("/data", Some("55"));
+        let hashes = merkle_hashes(&df);
+
+        assert_eq!(hashes.len(), 4);
+        assert!(hashes.values().all(Option::is_some));
+
+        for order in [
+            [8, 7, 6, 5, 4, 3, 2, 1, 0],
+            [7, 3, 0, 8, 5, 1, 6, 2, 4],
+            [4, 6, 2, 8, 0, 5, 7, 1, 3],
+        ] {
+            assert_eq!(merkle_hashes(&shuffled(&df, &order)), hashes);
+        }
+    }
+
+    #[test]
+    fn merkle_hashes_ignore_the_root() {
+        assert_eq!(
+            merkle_hashes(&index("/data", Some("55"))),
+            merkle_hashes(&index("/backup/data", Some("55")))
+        );
+    }
+
+    #[test]
+    fn content_changes_reach_the_root_only() {
+        let before = merkle_hashes(&index("/data", Some("55")));
+        let after = merkle_hashes(&index("/data", Some("66")));
+
+        assert_ne!(before["."], after["."]);
+        assert_ne!(before["a"], after["a"]);
+        assert_eq!(before["a/c"], after["a/c"]);
+        assert_eq!(before["b"], after["b"]);
+    }
+
+    #[test]
+    fn unhashed_files_leave_their_folders_without_hash() {
+        let hashes = merkle_hashes(&index("/data", None));
+
+        assert_eq!(hashes["."], None);
+        assert_eq!(hashes["a"], None);
+        assert!(hashes["a/c"].is_some());
+        assert!(hashes["b"].is_some());
+    }
+
+    #[test]
+    fn totals_cover_subfolders() {
+        let folders = folder_index(&index("/data", Some("55"))).unwrap();
+        let root = folders
+            .filter(&folders.column("relative_path").unwrap().equal(".").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            root.column("file_count").unwrap().u64().unwrap().get(0),
+            Some(6)
+        );
+        assert_eq!(
+            root.column("total_size").unwrap().u64().unwrap().get(0),
+            Some(50)
+        );
+        assert_eq!(
+            root.column("subfolder_count")
+                .unwrap()
+                .u64()
+                .unwrap()
+                .get(0),
+            Some(3)
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use polars::prelude::*;
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+use crate::path_data::FileType;
+use crate::utils::file_operations::read_parquet_batches;
+use crate::utils::hashing::HashAlgorithm;
+
+/// Columns of the previous cache needed to decide whether a path changed, and to reuse its entry
+/// if it didn't. The names and extensions follow from the path.
+const CACHE_COLUMNS: [&str; 14] = [
+    "path",
+    "size",
+    "allocated_size",
+    "created",
+    "modified",
+    "modified_nanos",
+    "is_folder",
+    "hash",
+    "hash_algorithm",
+    "file_type",
+    "link_target",
+    "device",
+    "inode",
+    "link_count",
+];
+
+/// A previously indexed path: what is needed to decide whether it changed, and to write it again.
+#[derive(Debug, Clone)]
+pub struct CachedEntry {
+    pub size: Option<u64>,
+    pub allocated_size: Option<u64>,
+    pub created: Option<i64>,
+    pub modified: Option<i64>,
+    pub modified_nanos: Option<u32>,
+    pub is_folder: bool,
+    pub hash: Option<String>,
+    pub hash_algorithm: Option<HashAlgorithm>,
+    pub file_type: FileType,
+    pub link_target: Option<PathBuf>,
+    pub device: Option<u64>,
+    pub inode: Option<u64>,
+    pub link_count: Option<u64>,
+}
+
+/// Lookup tables built from an earlier parquet cache, used for incremental re-indexing.
+/// Entries are kept by folder and name, so the full path of every entry is not held in memory.
+#[derive(Debug, Default)]
+pub struct IndexCache {
+    folders: HashMap<PathBuf, HashMap<OsString, CachedEntry>>,
+    path_count: usize,
+    /// Whether the cached folder listings can stand in for reading the folders again.
+    reuse_listings: bool,
+    /// When the previous run started.
+    started: Option<SystemTime>,
+}
+
+impl IndexCache {
+    /// Reads the previous cache one row group at a time, keeping only the columns it needs, so the
+    /// cache is never loaded as a whole. Folder listings are only reused with `reuse_listings`,
+    /// when the cache was walked with the same filter, and has every column of an entry.
+    /// `started` is when the previous run started, see `same_modified`.
+    pub fn load(
+        cache_file: &Path,
+        reuse_listings: bool,
+        started: Option<SystemTime>,
+    ) -> Result<Self> {
+        let mut cache = IndexCache {
+            reuse_listings,
+            started,
+            ..Default::default()
+        };
+        for batch in read_parquet_batches(cache_file, Some(&CACHE_COLUMNS))? {
+            cache.add(&batch?)?;
+        }
+        Ok(cache)
+    }
+
+    fn add(&mut self, df: &DataFrame) -> PolarsResult<()> {
+        // Caches from older versions lack some of the columns. Their entries can't be written
+        // again as they were, but their hashes can still be reused.
+        if CACHE_COLUMNS
+            .iter()
+            .any(|column| df.column(column).is_err())
+        {
+            self.reuse_listings = false;
+        }
+        let optional = |name: &str| df.column(name).ok();
+
+        let paths = df.column("path")?.str()?;
+        let sizes = df.column("size")?.u64()?;
+        let allocated_sizes = optional("allocated_size").map(|c| c.u64()).transpose()?;
+        let created = optional("created").map(|c| c.i64()).transpose()?;
+        let modified = df.column("modified")?.i64()?;
+        let modified_nanos = optional("modified_nanos").map(|c| c.u32()).transpose()?;
+        let is_folders = df.column("is_folder")?.bool()?;
+        let hashes = df.column("hash")?.str()?;
+        let hash_algorithms = optional("hash_algorithm").map(|c| c.str()).transpose()?;
+        let file_types = optional("file_type").map(|c| c.str()).transpose()?;
+        let link_targets = optional("link_target").map(|c| c.str()).transpose()?;
+        let devices = optional("device").map(|c| c.u64()).transpose()?;
+        let inodes = optional("inode").map(|c| c.u64()).transpose()?;
+        let link_counts = optional("link_count").map(|c| c.u64()).transpose()?;
+
+        for row in 0..df.height() {
+            let Some(path) = paths.get(row).map(Path::new) else {
+                continue;
+            };
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+
+            let is_folder = is_folders.get(row).unwrap_or(false);
+            let hash = hashes.get(row);
+            let hash_algorithm = match &hash_algorithms {
+                Some(hash_algorithms) => hash_algorithms
+                    .get(row)
+                    .and_then(|algorithm| algorithm.parse().ok()),
+                // Caches from before the hash algorithm could be chosen only have SHA256 hashes.
+                None => hash.map(|_| HashAlgorithm::Sha256),
+            };
+            let file_type = file_types
+                .and_then(|file_types| file_types.get(row))
+                .and_then(|file_type| file_type.parse().ok())
+                .unwrap_or(if is_folder {
+                    FileType::Folder
+                } else {
+                    FileType::Other
+                });
+
+            self.folders
+                .entry(parent.to_path_buf())
+                .or_default()
+                .insert(
+                    name.to_os_string(),
+                    CachedEntry {
+                        size: sizes.get(row),
+                        allocated_size: allocated_sizes.and_then(|sizes| sizes.get(row)),
+                        created: created.and_then(|created| created.get(row)),
+                        modified: modified.get(row),
+                        modified_nanos: modified_nanos.and_then(|nanos| nanos.get(row)),
+                        is_folder,
+                        hash: hash.map(str::to_string),
+                        hash_algorithm,
+                        file_type,
+                        link_target: link_targets
+                            .and_then(|targets| targets.get(row))
+                            .map(PathBuf::from),
+                        device: devices.and_then(|devices| devices.get(row)),
+                        inode: inodes.and_then(|inodes| inodes.get(row)),
+                        link_count: link_counts.and_then(|counts| counts.get(row)),
+                    },
+                );
+            self.path_count += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Number of paths in the cache.
+    pub fn path_count(&self) -> usize {
+        self.path_count
+    }
+
+    fn get(&self, path: &Path) -> Option<&CachedEntry> {
+        self.folders.get(path.parent()?)?.get(path.file_name()?)
+    }
+
+    /// Whether `modified` is still the modification time of the entry.
+    /// Without the nanoseconds, as on filesystems with whole-second timestamps and in caches from
+    /// older versions, a file written to in the second it was indexed in would look unchanged.
+    /// Such entries only count if they were modified before the previous run started.
+    fn same_modified(&self, entry: &CachedEntry, modified: Option<SystemTime>) -> bool {
+        let Some(modified) = modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) else {
+            return false;
+        };
+        if entry.modified != Some(modified.as_secs() as i64) {
+            return false;
+        }
+
+        let nanos = modified.subsec_nanos();
+        match entry.modified_nanos {
+            Some(cached) if cached != 0 => cached == nanos,
+            Some(_) if nanos != 0 => false,
+            _ => self.started.is_some_and(|started| {
+                started
+                    .duration_since(UNIX_EPOCH)
+                    .is_ok_and(|started| modified.as_secs() < started.as_secs())
+            }),
+        }
+    }
+
+    /// Returns the cached hash if the file still has the same size and modification time,
+    /// and was hashed with `algorithm`.
+    pub fn unchanged_hash(
+        &self,
+        path: &Path,
+        size: Option<u64>,
+        modified: Option<SystemTime>,
+        algorithm: HashAlgorithm,
+    ) -> Option<String> {
+        let entry = self.get(path)?;
+
+        if entry.is_folder || size.is_none() {
+            return None;
+        }
+
+        if entry.size == size
+            && self.same_modified(entry, modified)
+            && entry.hash_algorithm == Some(algorithm)
+        {
+            entry.hash.clone()
+        } else {
+            None
+        }
+    }
+
+    /// The entries of a folder in the previous run, if the folder's modification time is
+    /// unchanged. Adding, removing or renaming an entry updates the modification time of its
+    /// folder, so the listing still holds. Files written to in place, and changes in subfolders,
+    /// don't show up in it though.
+    pub fn unchanged_children(
+        &self,
+        folder_path: &Path,
+    ) -> Option<impl Iterator<Item = (&OsStr, &CachedEntry)>> {
+        if !self.reuse_listings {
+            return None;
+        }
+
+        let entry = self.get(folder_path)?;
+        let modified = folder_path.metadata().ok()?.modified().ok();
+
+        if !entry.is_folder || !self.same_modified(entry, modified) {
+            return None;
+        }
+
+        // Empty folders have no entries of their own.
+        Some(
+            self.folders
+                .get(folder_path)
+                .into_iter()
+                .flatten()
+                .map(|(name, entry)| (name.as_os_str(), entry)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cache(modified_nanos: Option<u32>, started: u64) -> IndexCache {
+        let mut df = df!(
+            "path" => ["/data/a"],
+            "size" => [Some(3u64)],
+            "modified" => [Some(100i64)],
+            "is_folder" => [false],
+            "hash" => [Some("aa11")]
+        )
+        .unwrap();
+        if let Some(nanos) = modified_nanos {
+            df.with_column(Series::new("modified_nanos", [nanos]))
+                .unwrap();
+        }
+
+        let mut cache = IndexCache {
+            started: Some(UNIX_EPOCH + Duration::from_secs(started)),
+            ..Default::default()
+        };
+        cache.add(&df).unwrap();
+        cache
+    }
+
+    fn hash(cache: &IndexCache, seconds: u64, nanos: u32) -> Option<String> {
+        cache.unchanged_hash(
+            Path::new("/data/a"),
+            Some(3),
+            Some(UNIX_EPOCH + Duration::new(seconds, nanos)),
+            HashAlgorithm::Sha256,
+        )
+    }
+
+    #[test]
+    fn compares_modification_times_to_the_nanosecond() {
+        let cache = cache(Some(500), 100);
+
+        assert_eq!(hash(&cache, 100, 500).as_deref(), Some("aa11"));
+        assert_eq!(hash(&cache, 100, 501), None);
+        assert_eq!(hash(&cache, 101, 500), None);
+    }
+
+    #[test]
+    fn whole_seconds_only_count_before_the_previous_run() {
+        // Written in the second the previous run started, so it may have changed after being read.
+        assert_eq!(hash(&cache(Some(0), 100), 100, 0), None);
+        assert_eq!(hash(&cache(None, 100), 100, 500), None);
+
+        assert_eq!(hash(&cache(Some(0), 101), 100, 0).as_deref(), Some("aa11"));
+        assert_eq!(hash(&cache(Some(0), 101), 100, 500), None);
+        assert_eq!(hash(&cache(None, 101), 100, 500).as_deref(), Some("aa11"));
+    }
+}
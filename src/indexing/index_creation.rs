@@ -1,13 +1,22 @@
 #[allow(unused)]
 use log::{error, info, warn};
+use polars::prelude::*;
 use rayon::{max_num_threads, prelude::*};
 
-use crate::path_data::PathData;
-use crate::utils::hashing::hash_file;
+use crate::error::{Error, Result};
+use crate::indexing::index_cache::{CachedEntry, IndexCache};
+use crate::indexing::index_options::{IndexOptions, Traversal};
+use crate::indexing::index_processing::from_unix_time;
+use crate::indexing::path_filter::IgnoreStack;
+use crate::path_data::{FileType, PathData};
+use crate::utils::file_operations::read_parquet_batches;
+use crate::utils::hashing::{hash_file, quick_hash_file, HashAlgorithm, QuickHash};
 
-use std::fs::read_dir;
-use std::io::{Error, ErrorKind};
+use std::collections::{HashMap, HashSet};
+use std::fs::{read_dir, read_link, Metadata};
+use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Instant, SystemTime};
 
@@ -16,17 +25,64 @@ use std::time::{Instant, SystemTime};
 /// If any conversions fail, this will return a None.
 fn path_os_str_to_string(path_os_str: Option<&std::ffi::OsStr>) -> Option<String> {
     match path_os_str {
-        Some(path) => match path.to_str() {
-            Some(path) => Some(path.to_string()),
-            None => None,
-        },
+        Some(path) => path.to_str().map(|path| path.to_string()),
         None => None,
     }
 }
 
+/// Device the entry is stored on.
+#[cfg(unix)]
+fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+/// Device IDs are only available on Unix.
+#[cfg(not(unix))]
+fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Bytes actually allocated on disk, which is smaller than the length for sparse or compressed files.
+/// Block counts are always in 512-byte units, regardless of the filesystem block size.
+#[cfg(unix)]
+fn allocated_size(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.blocks() * 512)
+}
+
+/// Block counts are only available on Unix.
+#[cfg(not(unix))]
+fn allocated_size(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Inode and number of hardlinks to it.
+#[cfg(unix)]
+fn inode_links(metadata: &Metadata) -> (Option<u64>, Option<u64>) {
+    use std::os::unix::fs::MetadataExt;
+
+    (Some(metadata.ino()), Some(metadata.nlink()))
+}
+
+/// Inodes are only available on Unix.
+#[cfg(not(unix))]
+fn inode_links(_metadata: &Metadata) -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
 /// Takes a &Path and extracts necessary information from the current path to populate PathData.
 /// Works for both folders and files.
-fn construct_entry(path: &Path, get_metadata: bool, get_hash: bool) -> Result<PathData, Error> {
+/// When a previous index is given, hashes are reused for files whose size and modification time are unchanged.
+/// Entries whose metadata or hash can't be read are still returned, and the error is recorded in `state`.
+fn construct_entry(
+    path: &Path,
+    options: &IndexOptions,
+    previous: Option<&IndexCache>,
+    state: &WalkState,
+) -> Result<PathData> {
     // PathBuf to save in the struct.
     let path_buf = path.to_path_buf();
 
@@ -40,156 +96,479 @@ fn construct_entry(path: &Path, get_metadata: bool, get_hash: bool) -> Result<Pa
     // If it fails, something is really wrong, so this will return an Error.
     let name = path
         .file_name()
-        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Failed to access path name"))?
+        .ok_or_else(|| {
+            Error::io(
+                path,
+                io::Error::new(ErrorKind::NotFound, "Failed to access path name"),
+            )
+        })?
         .to_str()
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Failed to convert to str"))?
+        .ok_or_else(|| {
+            Error::io(
+                path,
+                io::Error::new(ErrorKind::InvalidData, "Failed to convert to str"),
+            )
+        })?
         .to_string();
 
     // Stem is only for files.
     let stem = path_os_str_to_string(path.file_stem());
 
+    // Not following symlinks, so a link is recorded as the link itself rather than its target.
+    let link_metadata = path
+        .symlink_metadata()
+        .map_err(|e| state.record(Error::io(path, e)))
+        .ok();
+    let file_type = link_metadata
+        .as_ref()
+        .map_or(FileType::Other, |metadata| metadata.file_type().into());
+
+    let link_target = if file_type == FileType::Symlink {
+        read_link(path).ok()
+    } else {
+        None
+    };
+
+    // When following symlinks, everything except the file type describes the target.
+    let metadata = if file_type == FileType::Symlink && options.follow_symlinks {
+        path.metadata().ok()
+    } else {
+        link_metadata
+    };
+
     // Getting metadata options.
-    let (size, created, modified) = if get_metadata {
-        match path.metadata() {
-            Ok(metadata) => {
+    let (size, allocated_size, created, modified) = if options.get_metadata {
+        match &metadata {
+            Some(metadata) => {
                 let size = Some(metadata.len());
 
-                let created: Option<SystemTime> = match metadata.created() {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-                };
+                let allocated_size = allocated_size(metadata);
+
+                let created: Option<SystemTime> = metadata.created().ok();
 
-                let modified: Option<SystemTime> = match metadata.modified() {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-                };
+                let modified: Option<SystemTime> = metadata.modified().ok();
 
-                (size, created, modified)
+                (size, allocated_size, created, modified)
             }
-            Err(_) => (None, None, None),
+            None => (None, None, None, None),
         }
     } else {
-        (None, None, None)
+        (None, None, None, None)
     };
 
     // Extension from OsStr to String.
     let extension = path_os_str_to_string(path.extension());
 
-    let is_folder = path.is_dir();
+    let is_folder = metadata.as_ref().is_some_and(|metadata| metadata.is_dir());
+    let device = metadata.as_ref().and_then(device_id);
+    let (inode, link_count) = metadata.as_ref().map_or((None, None), inode_links);
 
-    let hash = if get_hash && !is_folder {
-        if let Ok(hash) = hash_file(path) {
-            Some(hash)
-        } else {
-            None
-        }
+    // Only regular files are hashed: reading from e.g. a named pipe could block forever.
+    let is_file = metadata.as_ref().is_some_and(|metadata| metadata.is_file());
+
+    let hash = if options.get_hash && is_file {
+        file_hash(path, size, modified, options.hash_algorithm, previous)
+            .map_err(|e| state.record(e))
+            .ok()
     } else {
         None
     };
+    let hash_algorithm = hash.as_ref().map(|_| options.hash_algorithm);
 
     // Creating a result.
     Ok(PathData::new(
-        path_buf, parent, name, stem, size, extension, created, modified, is_folder, hash,
+        path_buf,
+        parent,
+        name,
+        stem,
+        size,
+        allocated_size,
+        extension,
+        created,
+        modified,
+        is_folder,
+        hash,
+        hash_algorithm,
+        file_type,
+        link_target,
+        device,
+        inode,
+        link_count,
+    ))
+}
+
+/// Hashes a file, reusing the previous hash if the file is unchanged since the last index
+/// and was hashed with the same algorithm.
+fn file_hash(
+    path: &Path,
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+    algorithm: HashAlgorithm,
+    previous: Option<&IndexCache>,
+) -> Result<String> {
+    match previous.and_then(|cache| cache.unchanged_hash(path, size, modified, algorithm)) {
+        Some(hash) => Ok(hash),
+        None => hash_file(path, algorithm).map_err(|e| Error::io(path, e)),
+    }
+}
+
+/// Identifies a folder independently of the path it was reached through.
+#[cfg(unix)]
+type FolderId = (u64, u64);
+#[cfg(not(unix))]
+type FolderId = PathBuf;
+
+/// Device and inode of the folder, after following any symlinks.
+#[cfg(unix)]
+fn folder_id(folder_path: &Path) -> Option<FolderId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = folder_path.metadata().ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without inodes, the canonical path identifies the folder.
+#[cfg(not(unix))]
+fn folder_id(folder_path: &Path) -> Option<FolderId> {
+    std::fs::canonicalize(folder_path).ok()
+}
+
+/// State shared by all workers during a single walk.
+#[derive(Default)]
+struct WalkState {
+    /// Folders already indexed, only tracked when following symlinks.
+    visited_folders: Mutex<HashSet<FolderId>>,
+    /// Device of the indexed folder.
+    root_device: Option<u64>,
+    /// Folders on another device that were not descended into.
+    mount_boundaries: Mutex<Vec<PathBuf>>,
+    /// Paths that could not be read, or only partially.
+    errors: Mutex<Vec<Error>>,
+}
+
+impl WalkState {
+    fn record(&self, error: Error) {
+        self.errors.lock().unwrap().push(error);
+    }
+}
+
+/// Outcome of a walk: the number of paths indexed, and the errors for paths that could not be read.
+#[derive(Debug, Default)]
+pub struct IndexSummary {
+    pub path_count: usize,
+    pub errors: Vec<Error>,
+}
+
+/// A folder waiting to be indexed, with the ignore rules of its parent folders.
+type QueuedFolder = (PathBuf, Arc<IgnoreStack>);
+
+/// Turns a path into an index entry, queueing it for indexing if it is a folder.
+/// Folders on other devices are recorded but not queued when staying on one filesystem.
+fn push_entry(
+    path: &Path,
+    ignores: &Arc<IgnoreStack>,
+    folder_queue: &mut Vec<QueuedFolder>,
+    path_results: &mut Vec<PathData>,
+    options: &IndexOptions,
+    previous: Option<&IndexCache>,
+    state: &WalkState,
+) {
+    let index_entry = match construct_entry(path, options, previous, state) {
+        Ok(index_entry) => index_entry,
+        Err(e) => {
+            state.record(e);
+            return;
+        }
+    };
+
+    let is_mount_boundary =
+        options.one_file_system && index_entry.is_folder && index_entry.device != state.root_device;
+
+    if is_mount_boundary {
+        state
+            .mount_boundaries
+            .lock()
+            .unwrap()
+            .push(index_entry.path.to_owned());
+    }
+
+    // We need to save to two separate places so this is necessary only if we have a folder.
+    if index_entry.is_folder && !is_mount_boundary {
+        folder_queue.push((index_entry.path.to_owned(), ignores.clone()));
+    }
+
+    // Saving to the index reference vector.
+    path_results.push(index_entry);
+}
+
+/// Rebuilds the entry of a path in an unchanged folder from the previous index, without reading it.
+/// Returns None if the cached entry falls short, e.g. when it has no hash while hashing.
+fn reuse_entry(path: &Path, cached: &CachedEntry, options: &IndexOptions) -> Option<PathData> {
+    // Folders are always read again, to find out whether anything changed inside them.
+    if cached.is_folder {
+        return None;
+    }
+
+    // With `hash_duplicates`, the hashes are worked out again once all sizes are known.
+    let hash = if options.get_hash {
+        if cached.hash_algorithm != Some(options.hash_algorithm) {
+            return None;
+        }
+        Some(cached.hash.clone()?)
+    } else {
+        None
+    };
+    let hash_algorithm = hash.as_ref().map(|_| options.hash_algorithm);
+
+    Some(PathData::new(
+        path.to_path_buf(),
+        path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        path.file_name()?.to_str()?.to_string(),
+        path_os_str_to_string(path.file_stem()),
+        cached.size,
+        cached.allocated_size,
+        path_os_str_to_string(path.extension()),
+        cached.created.map(|seconds| from_unix_time(seconds, 0)),
+        cached
+            .modified
+            .map(|seconds| from_unix_time(seconds, cached.modified_nanos.unwrap_or(0))),
+        cached.is_folder,
+        hash,
+        hash_algorithm,
+        cached.file_type,
+        cached.link_target.clone(),
+        cached.device,
+        cached.inode,
+        cached.link_count,
     ))
 }
 
 /// Analyzes the contents of a folder, returning nested folders as well as paths found.
+/// Excluded paths are skipped before any metadata is read, so excluded folders are never descended into.
+/// In incremental mode, unchanged folders are not read again, see `IndexCache::unchanged_children`.
 fn index_folder(
     folder_path: &Path,
-    folder_queue: &mut Vec<PathBuf>,
+    parent_ignores: &Arc<IgnoreStack>,
+    folder_queue: &mut Vec<QueuedFolder>,
     path_results: &mut Vec<PathData>,
-    get_metadata: bool,
-    get_hash: bool,
+    options: &IndexOptions,
+    previous: Option<&IndexCache>,
+    state: &WalkState,
 ) {
+    // Symlinks can lead back to a parent folder, or to a folder that is indexed elsewhere already.
+    if options.follow_symlinks {
+        if let Some(folder_id) = folder_id(folder_path) {
+            if !state.visited_folders.lock().unwrap().insert(folder_id) {
+                info!("Skipping already indexed folder {:?}", folder_path);
+                return;
+            }
+        }
+    }
+
+    let ignores = options.filter.folder_ignores(folder_path, parent_ignores);
+
+    // The entries of an unchanged folder are taken from the previous run instead of reading them.
+    // Its subfolders are still checked, as changes inside them don't reach this folder.
+    if let Some(cached_children) = previous.and_then(|cache| cache.unchanged_children(folder_path))
+    {
+        for (name, cached) in cached_children {
+            let path = folder_path.join(name);
+
+            // Ignore files can change without changing the folders they apply to.
+            if options
+                .filter
+                .is_excluded(&path, cached.is_folder, &ignores)
+            {
+                continue;
+            }
+
+            match reuse_entry(&path, cached, options) {
+                Some(entry) => path_results.push(entry),
+                None => push_entry(
+                    &path,
+                    &ignores,
+                    folder_queue,
+                    path_results,
+                    options,
+                    previous,
+                    state,
+                ),
+            }
+        }
+        return;
+    }
+
     match read_dir(folder_path) {
         Ok(folder_contents) => {
             for path in folder_contents {
                 match path {
                     Ok(dir_entry) => {
-                        // Turning everything into a struct based on the entry.
-                        let index_entry_result =
-                            construct_entry(&dir_entry.path(), get_metadata, get_hash);
+                        let path = dir_entry.path();
 
-                        if let Ok(index_entry) = index_entry_result {
-                            // We need to save to two separate places so this is necessary only if we have a folder.
-                            if index_entry.is_folder {
-                                folder_queue.push(index_entry.path.to_owned());
-                            }
+                        // The file type usually comes with the folder listing, so this doesn't touch the entry itself.
+                        let is_dir = dir_entry
+                            .file_type()
+                            .is_ok_and(|file_type| file_type.is_dir());
 
-                            // Saving to the index reference vector.
-                            path_results.push(index_entry);
+                        if options.filter.is_excluded(&path, is_dir, &ignores) {
+                            continue;
                         }
+
+                        // Turning everything into a struct based on the entry.
+                        push_entry(
+                            &path,
+                            &ignores,
+                            folder_queue,
+                            path_results,
+                            options,
+                            previous,
+                            state,
+                        );
                     }
-                    Err(e) => {
-                        warn!("Failed to read path entry: {:?}", e);
-                    }
+                    Err(e) => state.record(Error::io(folder_path, e)),
                 }
             }
         }
-        Err(e) => {
-            warn!("Failed to read folder {:?}: {}.", folder_path, e)
+        Err(e) => state.record(Error::io(folder_path, e)),
+    }
+}
+
+/// Everything a worker needs to index a folder and hand over its entries.
+struct Walker<'a, F> {
+    options: &'a IndexOptions,
+    previous: Option<&'a IndexCache>,
+    state: WalkState,
+    on_batch: F,
+    paths_indexed_count: AtomicUsize,
+    /// Set once `on_batch` turns entries down, after which the remaining folders are skipped.
+    stopped: AtomicBool,
+}
+
+impl<F> Walker<'_, F>
+where
+    F: Fn(Vec<PathData>) -> bool + Sync,
+{
+    /// Indexes a single folder, passes its entries on, and returns the nested folders still to index.
+    fn index(&self, (folder_path, parent_ignores): QueuedFolder) -> Vec<QueuedFolder> {
+        if self.stopped.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+
+        let mut new_folders = Vec::new();
+        let mut results = Vec::new();
+
+        index_folder(
+            &folder_path,
+            &parent_ignores,
+            &mut new_folders,
+            &mut results,
+            self.options,
+            self.previous,
+            &self.state,
+        );
+
+        self.paths_indexed_count
+            .fetch_add(results.len(), Ordering::Relaxed);
+        if !(self.on_batch)(results) {
+            self.stopped.store(true, Ordering::Relaxed);
+            return Vec::new();
+        }
+
+        new_folders
+    }
+
+    /// Spawns a task per folder. Rayon's scheduler lets idle threads steal queued folders,
+    /// so threads keep busy until the whole tree is done instead of waiting on each other.
+    fn walk_work_stealing<'scope>(
+        &'scope self,
+        scope: &rayon::Scope<'scope>,
+        folder: QueuedFolder,
+    ) {
+        for new_folder in self.index(folder) {
+            scope.spawn(move |scope| self.walk_work_stealing(scope, new_folder));
+        }
+    }
+
+    /// Indexes the tree one depth at a time, waiting for every folder of a level to finish.
+    fn walk_level_by_level(&self, root: QueuedFolder) {
+        let mut folders = vec![root];
+
+        while !folders.is_empty() {
+            folders = folders
+                .into_par_iter()
+                .flat_map_iter(|folder| self.index(folder))
+                .collect();
         }
     }
 }
 
 /// Parallel processing code with a variable number of threads (default: max_num_threads() / 2, with a maximum of 20).
-/// Discovers folders, appends those to a shared queue, which the thread pool allocates workers to.
-pub fn create_index(index_path: &Path, get_metadata: bool, get_hash: bool) -> Vec<PathData> {
+/// Folders are scheduled over the thread pool as set by `options.traversal`.
+/// Passing the previous index enables incremental mode: unchanged folders are not read again,
+/// and hashes of unchanged files are reused.
+/// The entries of each folder are passed to `on_batch` as soon as the folder is read, so the index is
+/// never held in memory as a whole. If `on_batch` returns false, e.g. when the entries can't be
+/// written, the walk stops early.
+/// Paths that can't be read are skipped and returned as errors in the summary, the walk carries on without them.
+pub fn create_index<F>(
+    index_path: &Path,
+    options: &IndexOptions,
+    previous: Option<&IndexCache>,
+    on_batch: F,
+) -> Result<IndexSummary>
+where
+    F: Fn(Vec<PathData>) -> bool + Sync,
+{
     info!("Starting indexing at {:?}", index_path);
     let start = Instant::now();
 
-    let folder_queue = Arc::new(Mutex::new(vec![index_path.to_path_buf()]));
-    let path_index = Arc::new(Mutex::new(Vec::<PathData>::new()));
+    let root = (index_path.to_path_buf(), Arc::new(IgnoreStack::default()));
+    let walker = Walker {
+        options,
+        previous,
+        state: WalkState {
+            root_device: index_path.metadata().ok().as_ref().and_then(device_id),
+            ..Default::default()
+        },
+        on_batch,
+        paths_indexed_count: AtomicUsize::new(0),
+        stopped: AtomicBool::new(false),
+    };
+
+    if options.one_file_system && walker.state.root_device.is_none() {
+        warn!("Device IDs are unavailable: indexing across all filesystems.");
+    }
 
     // Just in case this is ran on a supercomputer, limiting the number of cores to 20.
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(std::cmp::min(max_num_threads() / 2, 20))
-        .build()
-        .unwrap();
-
-    pool.scope(|_| {
-        loop {
-            let folders = {
-                let mut queue = folder_queue.lock().unwrap();
-                if queue.is_empty() {
-                    break;
-                }
-                // Extract the folders to process in this iteration
-                queue.drain(..).collect::<Vec<_>>()
-            };
+        .build()?;
 
-            // Process folders in parallel
-            folders.into_par_iter().for_each(|folder_path| {
-                let mut new_folders = Vec::new();
-                let mut results = Vec::new();
-
-                index_folder(
-                    &folder_path,
-                    &mut new_folders,
-                    &mut results,
-                    get_metadata,
-                    get_hash,
-                );
-
-                // Safely update the shared folder_queue and path_results
-                {
-                    let mut queue = folder_queue.lock().unwrap();
-                    queue.extend(new_folders);
-                }
-                {
-                    let mut results_guard = path_index.lock().unwrap();
-                    results_guard.extend(results);
-                }
-            });
+    match options.traversal {
+        Traversal::WorkStealing => {
+            pool.scope(|scope| walker.walk_work_stealing(scope, root));
         }
-    });
+        Traversal::LevelByLevel => {
+            pool.install(|| walker.walk_level_by_level(root));
+        }
+    }
 
-    // Collecting all the data
-    let path_data = Arc::try_unwrap(path_index).unwrap().into_inner().unwrap();
+    let state = walker.state;
+    let paths_indexed_count = walker.paths_indexed_count;
+    let mount_boundaries = state.mount_boundaries.into_inner().unwrap();
+    if !mount_boundaries.is_empty() {
+        info!(
+            "Did not descend into {} folders on other filesystems:",
+            mount_boundaries.len()
+        );
+        for mount_boundary in &mount_boundaries {
+            info!("    {:?}", mount_boundary);
+        }
+    }
 
     // Printing some neat statistics
     let duration = start.elapsed();
-    let paths_indexed_count = path_data.len();
+    let paths_indexed_count = paths_indexed_count.into_inner();
 
     info!(
         "Indexed {} paths. Time taken: {:.3?} seconds. ({:.0} paths/s)",
@@ -198,5 +577,200 @@ pub fn create_index(index_path: &Path, get_metadata: bool, get_hash: bool) -> Ve
         (paths_indexed_count as f64 / duration.as_secs_f64())
     );
 
-    path_data
+    Ok(IndexSummary {
+        path_count: paths_indexed_count,
+        errors: state.errors.into_inner().unwrap(),
+    })
+}
+
+/// A file whose size matches another file, by its row in the cache.
+struct Candidate {
+    row: usize,
+    path: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Hashes found by `hash_size_collisions`, by row of the cache.
+#[derive(Debug)]
+pub struct CollisionHashes {
+    algorithm: HashAlgorithm,
+    hashes: HashMap<usize, String>,
+    quick_hashes: HashMap<usize, String>,
+    /// Files that could not be read, and are left unhashed.
+    pub errors: Vec<Error>,
+}
+
+impl CollisionHashes {
+    /// Sets the hash columns of a batch of the cache, whose first row is row `offset` of the cache.
+    pub fn apply(&self, df: &mut DataFrame, offset: usize) -> PolarsResult<()> {
+        let rows = offset..offset + df.height();
+
+        let hashes: StringChunked = rows
+            .clone()
+            .map(|row| self.hashes.get(&row).map(String::as_str))
+            .collect();
+        let hash_algorithms: StringChunked = rows
+            .clone()
+            .map(|row| self.hashes.get(&row).map(|_| self.algorithm.as_str()))
+            .collect();
+        let quick_hashes: StringChunked = rows
+            .map(|row| self.quick_hashes.get(&row).map(String::as_str))
+            .collect();
+        df.with_column(hashes.with_name("hash").into_series())?;
+        df.with_column(hash_algorithms.with_name("hash_algorithm").into_series())?;
+        df.with_column(quick_hashes.with_name("quick_hash").into_series())?;
+
+        Ok(())
+    }
+}
+
+/// Hashes only the files whose size matches at least one other file.
+/// Files with a unique size cannot have a duplicate, so this finds all duplicates without reading the whole tree.
+/// With `quick_hash`, those files get a quick hash first, and only files whose quick hash also matches
+/// another file are hashed completely.
+/// Symlinks are left out: their targets are compared where they are indexed themselves.
+/// Runs on the finished cache, which needs to be created with metadata. The cache is read one row
+/// group at a time, so only a count per size and the candidates are held in memory.
+/// Files that can't be read are left unhashed, and their errors are returned.
+pub fn hash_size_collisions(
+    cache_file: &Path,
+    algorithm: HashAlgorithm,
+    quick_hash: Option<QuickHash>,
+    previous: Option<&IndexCache>,
+) -> Result<CollisionHashes> {
+    let start = Instant::now();
+
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for batch in read_parquet_batches(cache_file, Some(&["size", "file_type"]))? {
+        let batch = batch?;
+        let sizes = batch.column("size")?.u64()?;
+        let file_types = batch.column("file_type")?.str()?;
+
+        for (size, file_type) in sizes.into_iter().zip(file_types) {
+            match size {
+                // Empty files are all alike, there is nothing to gain from hashing them.
+                Some(size) if size > 0 && file_type == Some(FileType::File.as_str()) => {
+                    *size_counts.entry(size).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut offset = 0;
+    let columns = ["path", "size", "modified", "modified_nanos", "file_type"];
+    for batch in read_parquet_batches(cache_file, Some(&columns))? {
+        let batch = batch?;
+        let paths = batch.column("path")?.str()?;
+        let sizes = batch.column("size")?.u64()?;
+        let modified = batch.column("modified")?.i64()?;
+        let modified_nanos = batch.column("modified_nanos")?.u32()?;
+        let file_types = batch.column("file_type")?.str()?;
+
+        for row in 0..batch.height() {
+            let (Some(path), Some(size)) = (paths.get(row), sizes.get(row)) else {
+                continue;
+            };
+            let is_candidate = file_types.get(row) == Some(FileType::File.as_str())
+                && size_counts.get(&size).is_some_and(|count| *count > 1);
+
+            if is_candidate {
+                candidates.push(Candidate {
+                    row: offset + row,
+                    path: PathBuf::from(path),
+                    size,
+                    modified: modified.get(row).map(|seconds| {
+                        from_unix_time(seconds, modified_nanos.get(row).unwrap_or(0))
+                    }),
+                });
+            }
+        }
+        offset += batch.height();
+    }
+
+    let errors = Mutex::new(Vec::new());
+
+    let quick_hashes: HashMap<usize, String> = match quick_hash {
+        Some(quick_hash) => candidates
+            .par_iter()
+            .filter_map(|candidate| {
+                quick_hash_file(&candidate.path, algorithm, quick_hash)
+                    .map_err(|e| errors.lock().unwrap().push(Error::io(&candidate.path, e)))
+                    .ok()
+                    .map(|hash| (candidate.row, hash))
+            })
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    let full_candidates: Vec<&Candidate> = match quick_hash {
+        Some(_) => duplicate_keys(0..candidates.len(), |index| {
+            let candidate = &candidates[index];
+            Some((candidate.size, quick_hashes.get(&candidate.row)?))
+        })
+        .into_iter()
+        .map(|index| &candidates[index])
+        .collect(),
+        None => candidates.iter().collect(),
+    };
+
+    let hashes: HashMap<usize, String> = full_candidates
+        .par_iter()
+        .filter_map(|candidate| {
+            file_hash(
+                &candidate.path,
+                Some(candidate.size),
+                candidate.modified,
+                algorithm,
+                previous,
+            )
+            .map_err(|e| errors.lock().unwrap().push(e))
+            .ok()
+            .map(|hash| (candidate.row, hash))
+        })
+        .collect();
+
+    if quick_hash.is_some() {
+        info!(
+            "Quick hashed {} files with matching sizes, of which {} needed a full hash. Time taken: {:.3?} seconds.",
+            candidates.len(),
+            full_candidates.len(),
+            start.elapsed().as_secs_f64()
+        );
+    } else {
+        info!(
+            "Hashed {} files with matching sizes. Time taken: {:.3?} seconds.",
+            candidates.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    Ok(CollisionHashes {
+        algorithm,
+        hashes,
+        quick_hashes,
+        errors: errors.into_inner().unwrap(),
+    })
+}
+
+/// Rows whose key is shared with at least one other row. Rows without a key are left out.
+fn duplicate_keys<K, I, F>(rows: I, key: F) -> Vec<usize>
+where
+    K: Eq + std::hash::Hash,
+    I: Iterator<Item = usize> + Clone,
+    F: Fn(usize) -> Option<K>,
+{
+    let mut key_counts: HashMap<K, usize> = HashMap::new();
+    for row in rows.clone() {
+        if let Some(key) = key(row) {
+            *key_counts.entry(key).or_default() += 1;
+        }
+    }
+
+    rows.filter(|row| {
+        key(*row).is_some_and(|key| key_counts.get(&key).is_some_and(|count| *count > 1))
+    })
+    .collect()
 }
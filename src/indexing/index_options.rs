@@ -0,0 +1,52 @@
+use crate::indexing::path_filter::PathFilter;
+use crate::utils::hashing::{HashAlgorithm, QuickHash};
+
+/// How the worker threads walk the folder tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Traversal {
+    /// Every folder is a task, and idle threads steal folders from busy ones.
+    #[default]
+    WorkStealing,
+    /// All folders of one depth are indexed before the next depth is started.
+    /// A single large folder holds up the whole level.
+    LevelByLevel,
+}
+
+impl std::str::FromStr for Traversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "work-stealing" => Ok(Traversal::WorkStealing),
+            "level" => Ok(Traversal::LevelByLevel),
+            _ => Err(format!("Unknown traversal: {}", s)),
+        }
+    }
+}
+
+/// Options controlling what is collected while indexing.
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// Include metadata (size, created, modified). This is slower than without metadata.
+    pub get_metadata: bool,
+    /// Hash every file.
+    pub get_hash: bool,
+    /// Hash function used for `get_hash` and `hash_duplicates`.
+    pub hash_algorithm: HashAlgorithm,
+    /// Only hash files whose size matches another file. Requires metadata.
+    pub hash_duplicates: bool,
+    /// With `hash_duplicates`, quick hash files with matching sizes first, and only fully hash those
+    /// whose quick hash matches as well.
+    pub quick_hash: Option<QuickHash>,
+    /// Reuse the existing cache: folders with an unchanged modification time aren't read again,
+    /// and files with an unchanged size and modification time keep their hash. Requires metadata.
+    pub incremental: bool,
+    /// Follow symlinks to folders and files. By default links are recorded but not followed.
+    pub follow_symlinks: bool,
+    /// Don't descend into folders on a different device than the indexed folder, like `du -x`.
+    pub one_file_system: bool,
+    /// Scheduling of folders over the worker threads.
+    pub traversal: Traversal,
+    /// Include/exclude patterns and ignore files applied during the walk.
+    pub filter: PathFilter,
+}
@@ -1,41 +1,182 @@
-use crate::indexing::index_creation::create_index;
+use crate::error::{Error, Result};
+use crate::indexing::cache_metadata::{recorded_start, same_walk, CacheMetadata};
+use crate::indexing::index_cache::IndexCache;
+use crate::indexing::index_creation::{create_index, hash_size_collisions};
+use crate::indexing::index_options::IndexOptions;
+use crate::indexing::index_writer::{temporary_file, IndexWriter, WRITE_QUEUE_SIZE};
 use crate::path_data::PathData;
+use crate::utils::hashing::HashAlgorithm;
+use chrono::Utc;
 use polars::prelude::*;
-use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+#[allow(unused)]
+use log::{error, info, warn};
+
+use std::fs::remove_file;
 use std::path::Path;
+use std::sync::mpsc::sync_channel;
+use std::thread;
 
-use crate::utils::file_operations::{_load_path_index_cache, save_path_index_cache};
+use crate::utils::file_operations::{
+    load_cache_metadata, read_parquet_batches, save_folder_index_cache,
+};
 
-/// Creates the path index, loads the Polars df, and saves the cache.
+/// Creates the path index, streams it to the cache, and saves the cumulative folder sizes next to it.
+/// Workers hand their entries to a writer thread, which writes them as parquet row groups and
+/// builds the folder index from them, so the whole index is never held in memory.
+/// In incremental mode, the existing cache in `cache_file` is used to skip unchanged work.
+/// With `hash_duplicates`, only files sharing their size with another file are hashed, after
+/// which the cache is rewritten one row group at a time with their hashes.
+/// Returns the paths that could not be read, which are left out or unhashed.
+/// If indexing fails, the previous cache is kept and the unfinished one removed.
 pub fn create_path_index(
     index_path: &Path,
-    cache_path: &Path,
-    get_metadata: bool,
-    get_hash: bool,
-) -> DataFrame {
-    let path_index = create_index(index_path, get_metadata, get_hash);
-    let df = to_polars_df(&path_index).expect("Failed to convert to Polars.");
-    save_path_index_cache(cache_path, &df);
-    df
+    cache_file: &Path,
+    options: &IndexOptions,
+) -> Result<Vec<Error>> {
+    let result = write_path_index(index_path, cache_file, options);
+    if result.is_err() {
+        let _ = remove_file(temporary_file(cache_file));
+    }
+    result
 }
 
-/// Currently unused: can load in from cache if that exists, or create if it doesn't.
-pub fn _create_or_from_cache(
+fn write_path_index(
     index_path: &Path,
-    cache_path: &Path,
-    get_metadata: bool,
-    get_hash: bool,
-) -> DataFrame {
-    if !cache_path.exists() {
-        create_path_index(index_path, cache_path, get_metadata, get_hash)
+    cache_file: &Path,
+    options: &IndexOptions,
+) -> Result<Vec<Error>> {
+    let started = Utc::now();
+
+    let previous = if options.incremental {
+        load_previous_index(cache_file, options)
+    } else {
+        None
+    };
+
+    let (sender, receiver) = sync_channel::<Vec<PathData>>(WRITE_QUEUE_SIZE);
+
+    let (writer, summary) = thread::scope(|scope| -> Result<_> {
+        let writer = scope.spawn(move || -> Result<IndexWriter> {
+            let mut writer = IndexWriter::create(cache_file, index_path)?;
+            for batch in receiver {
+                writer.write(batch)?;
+            }
+            Ok(writer)
+        });
+
+        // If the writer fails, the walk stops and the error is reported below.
+        let summary = create_index(index_path, options, previous.as_ref(), |batch| {
+            sender.send(batch).is_ok()
+        });
+        drop(sender);
+
+        let writer = writer.join().expect("Index writer panicked")?;
+        Ok((writer, summary?))
+    })?;
+    let mut errors = summary.errors;
+
+    let (entry_count, folder_count) = writer.counts();
+    let mut metadata = CacheMetadata::new(index_path, options, started, entry_count, folder_count);
+    let mut folder_df = writer.finish(&metadata.key_values())?;
+
+    // Sizes are only known once everything is indexed, so the candidates are hashed afterwards.
+    if options.hash_duplicates && !options.get_hash {
+        let hashes = hash_size_collisions(
+            cache_file,
+            options.hash_algorithm,
+            options.quick_hash,
+            previous.as_ref(),
+        )?;
+
+        let mut writer = IndexWriter::create(cache_file, index_path)?;
+        let mut offset = 0;
+        for batch in read_parquet_batches(cache_file, None)? {
+            let mut batch = batch?;
+            hashes.apply(&mut batch, offset)?;
+            offset += batch.height();
+            writer.write_df(&batch)?;
+        }
+
+        metadata = CacheMetadata::new(index_path, options, started, entry_count, folder_count);
+        folder_df = writer.finish(&metadata.key_values())?;
+        errors.extend(hashes.errors);
+    }
+
+    save_folder_index_cache(cache_file, &folder_df, &metadata)?;
+
+    Ok(errors)
+}
+
+/// Loads the previous cache for incremental indexing, falling back to a full index if there is none.
+/// If the cache was walked with other filter or symlink options, its folder listings hold other
+/// entries, so every folder is read again and only the hashes are reused.
+fn load_previous_index(cache_file: &Path, options: &IndexOptions) -> Option<IndexCache> {
+    if !cache_file.exists() {
+        warn!(
+            "No existing cache at {:?}: creating a full index instead.",
+            cache_file
+        );
+        return None;
+    }
+
+    let key_values = load_cache_metadata(cache_file).unwrap_or_default();
+    let reuse_listings = same_walk(&key_values, options);
+    if !reuse_listings {
+        info!(
+            "{:?} was indexed with other filter or symlink options: reading every folder again.",
+            cache_file
+        );
+    }
+
+    let started = recorded_start(&key_values).map(SystemTime::from);
+    match IndexCache::load(cache_file, reuse_listings, started) {
+        Ok(previous) => {
+            info!(
+                "Loaded {} cached paths from {:?}",
+                previous.path_count(),
+                cache_file
+            );
+            Some(previous)
+        }
+        Err(e) => {
+            warn!(
+                "Failed to use cache {:?}: {}. Creating a full index.",
+                cache_file, e
+            );
+            None
+        }
+    }
+}
+
+/// Converts a timestamp to whole seconds since the Unix epoch, as stored in the cache.
+pub fn unix_seconds(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|dur| dur.as_secs() as i64)
+}
+
+/// The nanoseconds within the second of a timestamp, stored next to the whole seconds so that
+/// changes within a second can be told apart.
+pub fn subsec_nanos(time: SystemTime) -> Option<u32> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|dur| dur.subsec_nanos())
+}
+
+/// Converts seconds and nanoseconds since the Unix epoch, as stored in the cache, back to a timestamp.
+pub fn from_unix_time(seconds: i64, nanos: u32) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::new(seconds as u64, nanos)
     } else {
-        _load_path_index_cache(cache_path)
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+            + Duration::from_nanos(nanos as u64)
     }
 }
 
 /// Conversion of the vectors to a Polars DataFrame for further analysis.
-pub fn to_polars_df(path_index: &Vec<PathData>) -> Result<DataFrame, PolarsError> {
+pub fn to_polars_df(path_index: &[PathData]) -> PolarsResult<DataFrame> {
     let paths: Vec<String> = path_index
         .iter()
         .map(|d| d.path.to_string_lossy().into_owned())
@@ -47,29 +188,38 @@ pub fn to_polars_df(path_index: &Vec<PathData>) -> Result<DataFrame, PolarsError
     let names: Vec<String> = path_index.iter().map(|d| d.name.clone()).collect();
     let stems: Vec<Option<String>> = path_index.iter().map(|d| d.stem.clone()).collect();
     let sizes: Vec<Option<u64>> = path_index.iter().map(|d| d.size).collect();
+    let allocated_sizes: Vec<Option<u64>> = path_index.iter().map(|d| d.allocated_size).collect();
     let extensions: Vec<Option<String>> = path_index.iter().map(|d| d.extension.clone()).collect();
     let created: Vec<Option<i64>> = path_index
         .iter()
-        .map(|d| {
-            d.created.and_then(|t| {
-                t.duration_since(UNIX_EPOCH)
-                    .ok()
-                    .map(|dur| dur.as_secs() as i64)
-            })
-        })
+        .map(|d| d.created.and_then(unix_seconds))
         .collect();
     let modified: Vec<Option<i64>> = path_index
         .iter()
-        .map(|d| {
-            d.modified.and_then(|t| {
-                t.duration_since(UNIX_EPOCH)
-                    .ok()
-                    .map(|dur| dur.as_secs() as i64)
-            })
-        })
+        .map(|d| d.modified.and_then(unix_seconds))
+        .collect();
+    let modified_nanos: Vec<Option<u32>> = path_index
+        .iter()
+        .map(|d| d.modified.and_then(subsec_nanos))
         .collect();
     let is_folders: Vec<bool> = path_index.iter().map(|d| d.is_folder).collect();
     let hash: Vec<Option<String>> = path_index.iter().map(|d| d.hash.clone()).collect();
+    let hash_algorithms: Vec<Option<&str>> = path_index
+        .iter()
+        .map(|d| d.hash_algorithm.as_ref().map(HashAlgorithm::as_str))
+        .collect();
+    let file_types: Vec<&str> = path_index.iter().map(|d| d.file_type.as_str()).collect();
+    let link_targets: Vec<Option<String>> = path_index
+        .iter()
+        .map(|d| {
+            d.link_target
+                .as_ref()
+                .map(|target| target.to_string_lossy().into_owned())
+        })
+        .collect();
+    let devices: Vec<Option<u64>> = path_index.iter().map(|d| d.device).collect();
+    let inodes: Vec<Option<u64>> = path_index.iter().map(|d| d.inode).collect();
+    let link_counts: Vec<Option<u64>> = path_index.iter().map(|d| d.link_count).collect();
 
     let df = DataFrame::new(vec![
         Series::new("path", paths),
@@ -77,11 +227,21 @@ pub fn to_polars_df(path_index: &Vec<PathData>) -> Result<DataFrame, PolarsError
         Series::new("name", names),
         Series::new("stem", stems),
         Series::new("size", sizes),
+        Series::new("allocated_size", allocated_sizes),
         Series::new("extension", extensions),
         Series::new("created", created),
         Series::new("modified", modified),
+        Series::new("modified_nanos", modified_nanos),
         Series::new("is_folder", is_folders),
         Series::new("hash", hash),
+        Series::new("hash_algorithm", hash_algorithms),
+        // Quick hashes are only computed afterwards, for files with matching sizes.
+        Series::full_null("quick_hash", path_index.len(), &DataType::String),
+        Series::new("file_type", file_types),
+        Series::new("link_target", link_targets),
+        Series::new("device", devices),
+        Series::new("inode", inodes),
+        Series::new("link_count", link_counts),
     ])?;
 
     Ok(df)
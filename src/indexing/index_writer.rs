@@ -0,0 +1,113 @@
+use polars::io::parquet::write::BatchedWriter;
+use polars::prelude::*;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use std::fs::{rename, File};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::indexing::folder_index::FolderIndexBuilder;
+use crate::indexing::index_processing::to_polars_df;
+use crate::path_data::PathData;
+use crate::utils::file_operations::finish_parquet;
+
+/// Entries collected before they are written as one parquet row group.
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Batches of entries waiting for the writer. Indexing pauses when the writer falls behind,
+/// which keeps the memory use bounded.
+pub const WRITE_QUEUE_SIZE: usize = 64;
+
+/// Writes the index to a parquet cache while it is being created, one row group at a time.
+/// The cache is written to a temporary file first, so an interrupted run keeps the previous cache.
+/// The folder index is built from the same row groups, so the entries are never all held in memory.
+pub struct IndexWriter {
+    writer: BatchedWriter<File>,
+    cache_file: PathBuf,
+    temporary_file: PathBuf,
+    buffer: Vec<PathData>,
+    entry_count: usize,
+    folder_count: usize,
+    folder_index: FolderIndexBuilder,
+}
+
+/// Where the cache is written until it is finished.
+pub fn temporary_file(cache_file: &Path) -> PathBuf {
+    cache_file.with_extension("parquet.tmp")
+}
+
+impl IndexWriter {
+    pub fn create(cache_file: &Path, index_path: &Path) -> Result<Self> {
+        let temporary_file = temporary_file(cache_file);
+
+        // The schema is the same for every batch, so an empty one is enough.
+        let schema = to_polars_df(&[])?.schema();
+        let file = File::create(&temporary_file).map_err(|e| Error::io(&temporary_file, e))?;
+        let writer = ParquetWriter::new(file).batched(&schema)?;
+
+        Ok(IndexWriter {
+            writer,
+            cache_file: cache_file.to_path_buf(),
+            temporary_file,
+            buffer: Vec::with_capacity(ROW_GROUP_SIZE),
+            entry_count: 0,
+            folder_count: 0,
+            folder_index: FolderIndexBuilder::new(index_path),
+        })
+    }
+
+    /// Adds entries to the cache, writing a row group whenever enough entries are buffered.
+    pub fn write(&mut self, batch: Vec<PathData>) -> Result<()> {
+        self.entry_count += batch.len();
+        self.folder_count += batch.iter().filter(|entry| entry.is_folder).count();
+        self.buffer.extend(batch);
+
+        if self.buffer.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            let df = to_polars_df(&self.buffer)?;
+            self.buffer.clear();
+            self.write_row_group(&df)?;
+        }
+        Ok(())
+    }
+
+    /// Adds entries that are already a DataFrame as one row group, e.g. when rewriting a cache.
+    pub fn write_df(&mut self, df: &DataFrame) -> Result<()> {
+        self.flush()?;
+        self.entry_count += df.height();
+        self.folder_count += df.column("is_folder")?.bool()?.sum().unwrap_or(0) as usize;
+        self.write_row_group(df)
+    }
+
+    fn write_row_group(&mut self, df: &DataFrame) -> Result<()> {
+        self.folder_index.add(df)?;
+        self.writer.write_batch(df)?;
+        Ok(())
+    }
+
+    /// Number of entries and folders written so far.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.entry_count, self.folder_count)
+    }
+
+    /// Writes the remaining entries and the metadata, and replaces the previous cache.
+    /// Returns the folder index of everything written.
+    pub fn finish(mut self, metadata: &[(String, String)]) -> Result<DataFrame> {
+        self.flush()?;
+        finish_parquet(self.writer, metadata)?;
+
+        info!("Saving cache: {:?}", self.cache_file);
+        rename(&self.temporary_file, &self.cache_file)
+            .map_err(|e| Error::io(&self.cache_file, e))?;
+
+        Ok(self.folder_index.finish()?)
+    }
+}
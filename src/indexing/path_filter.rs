@@ -0,0 +1,146 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::Match;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use std::path::Path;
+use std::sync::Arc;
+
+/// Ignore files honoured in every folder when ignore files are enabled.
+/// `.rfiignore` is specific to this tool, so paths can be skipped without touching version control.
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".rfiignore"];
+
+/// Decides which paths are skipped during the walk, before any metadata is read.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    overrides: Override,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    use_ignore_files: bool,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        PathFilter {
+            overrides: Override::empty(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            use_ignore_files: false,
+        }
+    }
+}
+
+/// The ignore rules of a folder, linked to those of its parent folders.
+#[derive(Debug, Default)]
+pub struct IgnoreStack {
+    gitignore: Option<Gitignore>,
+    parent: Option<Arc<IgnoreStack>>,
+}
+
+impl IgnoreStack {
+    /// The rules of the deepest folder that has an opinion on the path win, like in git.
+    fn matched(&self, path: &Path, is_dir: bool) -> Match<()> {
+        let mut current = Some(self);
+
+        while let Some(stack) = current {
+            if let Some(gitignore) = &stack.gitignore {
+                let matched = gitignore.matched(path, is_dir);
+                if !matched.is_none() {
+                    return matched.map(|_| ());
+                }
+            }
+            current = stack.parent.as_deref();
+        }
+
+        Match::None
+    }
+}
+
+impl PathFilter {
+    /// Gitignore-style globs relative to the root: `include` keeps only matching files
+    /// (folders are still descended into), while `exclude` skips matching files and folders.
+    pub fn new(
+        root: &Path,
+        include: &[String],
+        exclude: &[String],
+        use_ignore_files: bool,
+    ) -> Result<Self, ignore::Error> {
+        let mut builder = OverrideBuilder::new(root);
+
+        for pattern in include {
+            builder.add(pattern)?;
+        }
+        for pattern in exclude {
+            builder.add(&format!("!{}", pattern))?;
+        }
+
+        Ok(PathFilter {
+            overrides: builder.build()?,
+            include: include.to_vec(),
+            exclude: exclude.to_vec(),
+            use_ignore_files,
+        })
+    }
+
+    pub fn include(&self) -> &[String] {
+        &self.include
+    }
+
+    pub fn exclude(&self) -> &[String] {
+        &self.exclude
+    }
+
+    pub fn uses_ignore_files(&self) -> bool {
+        self.use_ignore_files
+    }
+
+    /// Include/exclude patterns take precedence over the ignore files.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool, ignores: &IgnoreStack) -> bool {
+        match self.overrides.matched(path, is_dir) {
+            Match::Ignore(_) => true,
+            Match::Whitelist(_) => false,
+            Match::None => self.use_ignore_files && ignores.matched(path, is_dir).is_ignore(),
+        }
+    }
+
+    /// Adds the rules from the ignore files found in `folder_path` on top of the parent rules.
+    pub fn folder_ignores(
+        &self,
+        folder_path: &Path,
+        parent: &Arc<IgnoreStack>,
+    ) -> Arc<IgnoreStack> {
+        if !self.use_ignore_files {
+            return parent.clone();
+        }
+
+        let mut builder = GitignoreBuilder::new(folder_path);
+        let mut found = false;
+
+        for ignore_file_name in IGNORE_FILE_NAMES {
+            let ignore_file = folder_path.join(ignore_file_name);
+            if ignore_file.is_file() {
+                found = true;
+                if let Some(e) = builder.add(&ignore_file) {
+                    warn!("Failed to parse ignore file {:?}: {}", ignore_file, e);
+                }
+            }
+        }
+
+        if !found {
+            return parent.clone();
+        }
+
+        match builder.build() {
+            Ok(gitignore) => Arc::new(IgnoreStack {
+                gitignore: Some(gitignore),
+                parent: Some(parent.clone()),
+            }),
+            Err(e) => {
+                warn!("Failed to use ignore files in {:?}: {}", folder_path, e);
+                parent.clone()
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod analysis;
+pub mod browse;
+pub mod error;
 pub mod indexing;
 pub mod path_data;
+pub mod query;
 pub mod utils;
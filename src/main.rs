@@ -1,15 +1,39 @@
 #![allow(unused)]
 
-use clap::{arg, command, Arg, ArgAction};
+use clap::{arg, command, value_parser, Arg, ArgAction, ArgMatches, Command};
 use env_logger::{Builder, Env};
+use polars::prelude::DataFrame;
 
-use rust_folder_analysis::analysis::analysis::run_analysis;
+use rust_folder_analysis::analysis::analysis::{
+    check_analysis_cache, run_analysis, AnalysisOptions, SizeMetric,
+};
+use rust_folder_analysis::analysis::diff::run_diff;
+use rust_folder_analysis::browse::browser::run_browser;
+use rust_folder_analysis::browse::file_tree::FileTree;
+use rust_folder_analysis::indexing::folder_index::{folder_index, index_root};
+use rust_folder_analysis::indexing::index_options::IndexOptions;
 use rust_folder_analysis::indexing::index_processing::create_path_index;
-use rust_folder_analysis::utils::file_operations::check_valid_folder_path;
-use rust_folder_analysis::utils::hashing::hash_file;
+use rust_folder_analysis::indexing::path_filter::PathFilter;
+use rust_folder_analysis::query::expression::parse_where;
+use rust_folder_analysis::query::filter::{run_query, QueryFilter};
+use rust_folder_analysis::query::output::{write_results, OutputFormat};
+use rust_folder_analysis::query::sql::run_sql;
+use rust_folder_analysis::utils::file_operations::{
+    cache_file_path, check_valid_file_path, check_valid_folder_path, default_cache_name,
+    load_cache_metadata, load_path_index_cache, print_and_save, ResultFormat,
+};
+use rust_folder_analysis::utils::hashing::{hash_file, HashAlgorithm, QuickHash};
+use rust_folder_analysis::utils::manifest::{
+    cache_hash_algorithm, guess_hash_algorithm, manifest_entries, read_manifest, write_manifest,
+};
+use rust_folder_analysis::utils::verification::{verify_cache, verify_manifest};
 
 use std::env::current_dir;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{stdout, BufReader, BufWriter};
 use std::path::{Path, PathBuf};
+use std::process::exit;
 
 #[allow(unused)]
 use log::{error, info, warn};
@@ -19,15 +43,209 @@ fn main() {
 
     // CLI options.
     let matches = command!()
-        .arg(arg!([index_path] "Folder path to start recursive indexing from.").required(true))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(index_command())
+        .subcommand(
+            Command::new("analyze")
+                .about("Run the Polars analysis on an existing parquet cache, without indexing again.")
+                .arg(
+                    Arg::new("from_cache")
+                        .long("from-cache")
+                        .value_name("FILE")
+                        .help("Parquet cache to analyze, created with index -m.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .short('H')
+                        .long("hash")
+                        .help("Report the overall hash. Requires a cache where every file was hashed.")
+                        .action(ArgAction::SetTrue),
+                )
+                .args(analysis_args()),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare two parquet caches of the same folder.")
+                .arg(arg!(<old_cache> "Parquet cache of the earlier index."))
+                .arg(arg!(<new_cache> "Parquet cache of the later index."))
+                .arg(analysis_folder_arg())
+                .arg(result_format_arg()),
+        )
+        .subcommand(
+            Command::new("browse")
+                .about("Browse a parquet cache interactively, like ncdu.")
+                .arg(arg!(<cache> "Parquet cache to browse.")),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show how and when a parquet cache was created.")
+                .arg(arg!(<cache> "Parquet cache to describe.")),
+        )
+        .subcommand(query_command())
+        .subcommand(
+            Command::new("sql")
+                .about("Run a SQL query against one or more parquet caches.")
+                .arg(arg!(<query> "SQL query, e.g. \"SELECT extension, SUM(size) FROM files GROUP BY extension\"."))
+                .arg(
+                    Arg::new("cache")
+                        .short('c')
+                        .long("cache")
+                        .value_name("[NAME=]FILE")
+                        .help("Parquet cache to register as table NAME (files by default), with its folder sizes as NAME_folders (folders). Can be repeated.")
+                        .required(true)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Save the results to this file, in the format given with --format."),
+                )
+                .arg(result_format_arg()),
+        )
+        .subcommand(
+            Command::new("hash")
+                .about("Print the hash of one or more files, like sha256sum.")
+                .arg(hash_algorithm_arg().short('a').long("algorithm"))
+                .arg(
+                    Arg::new("files")
+                        .help("Files to hash.")
+                        .required(true)
+                        .num_args(1..),
+                ),
+        )
+        .subcommand(
+            Command::new("manifest")
+                .about("Export the hashes in a parquet cache as a checksum manifest, like sha256sum or b3sum.")
+                .arg(arg!(<cache> "Parquet cache with hashes, created with -H."))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Write the manifest to this file instead of the terminal."),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Re-hash the files in a parquet cache or checksum manifest, and report files that changed, went missing or were added.")
+                .arg(arg!(<source> "Parquet cache created with -H, or a checksum manifest such as SHA256SUMS."))
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .help("Folder the manifest paths are relative to. Defaults to the folder of the manifest."),
+                )
+                .arg(
+                    hash_algorithm_arg()
+                        .short('a')
+                        .long("algorithm")
+                        .help("Hash function of the manifest. By default it is guessed from the hash length, where 64 characters means sha256, so b3sum manifests need -a blake3.")
+                        .default_value(None),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("index", index_matches)) => index(index_matches),
+        Some(("analyze", analyze_matches)) => analyze(analyze_matches),
+        Some(("diff", diff_matches)) => diff(diff_matches),
+        Some(("browse", browse_matches)) => browse(browse_matches),
+        Some(("info", info_matches)) => info(info_matches),
+        Some(("query", query_matches)) => query(query_matches),
+        Some(("sql", sql_matches)) => sql(sql_matches),
+        Some(("hash", hash_matches)) => hash(hash_matches),
+        Some(("manifest", manifest_matches)) => manifest(manifest_matches),
+        Some(("verify", verify_matches)) => verify(verify_matches),
+        _ => unreachable!("Clap requires a subcommand"),
+    }
+}
+
+fn analysis_folder_arg() -> Arg {
+    Arg::new("analysis_folder")
+        .short('r')
+        .long("analysis-folder")
+        // The old spelling keeps existing scripts working.
+        .alias("analysis_folder")
+        .help("Location to save the resulting CSVs. Defaults to the executable directory.")
+}
+
+fn result_format_arg() -> Arg {
+    Arg::new("format")
+        .short('f')
+        .long("format")
+        .help("File format of the saved results.")
+        .value_parser(["csv", "tsv", "json", "ndjson", "parquet", "markdown"])
+        .default_value("csv")
+}
+
+/// Hash function for file contents. The flags differ per subcommand.
+fn hash_algorithm_arg() -> Arg {
+    Arg::new("hash_algorithm")
+        .help("Hash function: sha256 and blake3 are cryptographic, sha1, md5 and crc32 match existing checksum files, xxh3 is the fastest.")
+        .value_parser(HashAlgorithm::NAMES)
+        .default_value("sha256")
+}
+
+/// Hash function given with `hash_algorithm_arg`.
+fn hash_algorithm(matches: &ArgMatches) -> HashAlgorithm {
+    matches
+        .get_one::<String>("hash_algorithm")
+        .expect("Hash algorithm has a default")
+        .parse()
+        .expect("Clap only allows known hash algorithms")
+}
+
+/// File format given with `--format`.
+fn result_format(matches: &ArgMatches) -> ResultFormat {
+    matches
+        .get_one::<String>("format")
+        .expect("Format has a default")
+        .parse()
+        .expect("Clap only allows known formats")
+}
+
+/// Options shared by `index -a` and `analyze`.
+fn analysis_args() -> Vec<Arg> {
+    vec![
+        analysis_folder_arg(),
+        result_format_arg(),
+        Arg::new("html_report")
+            .long("html")
+            .help("Save all analysis results in a single, offline HTML report in the analysis folder.")
+            .action(ArgAction::SetTrue),
+        Arg::new("size_metric")
+            .long("size-metric")
+            .help("Rank files and folders in the analysis by apparent size or by allocated disk space.")
+            .value_parser(["apparent", "allocated"])
+            .default_value("apparent"),
+        Arg::new("treemap")
+            .short('t')
+            .long("treemap")
+            .help("Render a treemap of the folder sizes, colored by extension, to SVG and PNG in the analysis folder.")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn index_command() -> Command {
+    Command::new("index")
+        .about("Recursively index a folder and save the results to a parquet cache.")
+        .arg(arg!(<index_path> "Folder path to start recursive indexing from."))
         .arg(
             Arg::new("cache_location")
                 .short('c')
-                .long("cache_location")
+                .long("cache-location")
+                .alias("cache_location")
                 .help(
                     "Location to save the parquet cache to. Defaults to the executable directory.",
                 ),
         )
+        .arg(
+            Arg::new("cache_name")
+                .short('n')
+                .long("cache-name")
+                .help("Name of the parquet cache. Defaults to a name derived from the indexed folder, so different folders can share a cache location."),
+        )
         .arg(
             Arg::new("metadata")
                 .short('m')
@@ -39,14 +257,15 @@ fn main() {
             Arg::new("analysis")
                 .short('a')
                 .long("analysis")
-                .help("Run Polars analysis code following the indexing operation.")
+                .help("Run Polars analysis code following the indexing operation. Requires metadata.")
+                .requires("metadata")
                 .action(ArgAction::SetTrue),
         )
-        .arg(
-            Arg::new("analysis_folder")
-                .short('r')
-                .long("analysis_folder")
-                .help("Location to save the resulting CSVs. Defaults to the executable directory."),
+        .args(
+            // The analysis options only make sense when analysing right away.
+            analysis_args()
+                .into_iter()
+                .map(|arg| arg.requires("analysis")),
         )
         .arg(
             Arg::new("hash")
@@ -55,50 +274,661 @@ fn main() {
                 .help("Calculate hashes per file")
                 .action(ArgAction::SetTrue),
         )
-        .get_matches();
+        .arg(
+            Arg::new("duplicates")
+                .short('D')
+                .long("duplicates")
+                .help("Only hash files whose size matches another file: enough to find duplicates, without hashing everything. Requires metadata.")
+                .requires("metadata")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(hash_algorithm_arg().long("hash-algorithm"))
+        .arg(
+            Arg::new("quick_hash")
+                .short('q')
+                .long("quick-hash")
+                .help("With -D, hash only the start and end of files with matching sizes first, and fully hash just the files whose quick hash matches too.")
+                .requires("duplicates")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quick_hash_size")
+                .long("quick-hash-size")
+                .value_name("KIB")
+                .help("KiB read from the start and from the end of a file for its quick hash, and per sampled block.")
+                .requires("quick_hash")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("64"),
+        )
+        .arg(
+            Arg::new("quick_hash_samples")
+                .long("quick-hash-samples")
+                .value_name("N")
+                .help("Blocks sampled at evenly spaced offsets between the start and end, for files that only differ in the middle.")
+                .requires("quick_hash")
+                .value_parser(value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("incremental")
+                .short('i')
+                .long("incremental")
+                .help("Reuse the existing cache: skip unchanged folders and reuse hashes of unchanged files. Requires metadata.")
+                .requires("metadata")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("follow_symlinks")
+                .short('L')
+                .long("follow-symlinks")
+                .help("Follow symlinks to folders and files. Folders reached twice, e.g. through a symlink loop, are only indexed once.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("one_file_system")
+                .short('x')
+                .long("one-file-system")
+                .help("Don't descend into folders on other filesystems, such as network mounts or /proc.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("traversal")
+                .long("traversal")
+                .help("How folders are scheduled over the threads: work-stealing, or level by level (one depth at a time).")
+                .value_parser(["work-stealing", "level"])
+                .default_value("work-stealing"),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .help("Skip paths matching this gitignore-style glob, e.g. node_modules or *.tmp. Can be repeated.")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .help("Only index files matching this gitignore-style glob. Folders are still searched. Can be repeated.")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("ignore_files")
+                .long("ignore-files")
+                .help("Honour .gitignore, .ignore and .rfiignore files while indexing.")
+                .action(ArgAction::SetTrue),
+        )
+}
 
+fn query_command() -> Command {
+    Command::new("query")
+        .about("Filter a parquet cache and print or save the matching paths.")
+        .arg(arg!(<cache> "Parquet cache to query."))
+        .arg(
+            Arg::new("path_prefix")
+                .long("path-prefix")
+                .help("Only paths starting with this prefix."),
+        )
+        .arg(
+            Arg::new("extension")
+                .short('e')
+                .long("extension")
+                .help("Only files with this extension, e.g. pdf. Can be repeated.")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("min_size")
+                .long("min-size")
+                .help("Minimum size in bytes.")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("max_size")
+                .long("max-size")
+                .help("Maximum size in bytes.")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("files_only")
+                .long("files-only")
+                .help("Only return files.")
+                .conflicts_with("folders_only")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("folders_only")
+                .long("folders-only")
+                .help("Only return folders.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("where")
+                .short('w')
+                .long("where")
+                .value_name("EXPRESSION")
+                .help("Filter expression, e.g. \"ext = log and size > 1G and mtime < 90d\". Can be repeated.")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .help("Output format. paths0 separates paths with NUL, for xargs -0.")
+                .value_parser(["table", "csv", "ndjson", "paths", "paths0"])
+                .default_value("table"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("Write the results to this file instead of the terminal."),
+        )
+}
+
+/// Unwraps the result of a library call, or logs the error and exits.
+fn or_exit<T, E: Display>(result: Result<T, E>, context: &str) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}: {}", context, e);
+        exit(1);
+    })
+}
+
+/// Folder given with `-r`, or the current directory.
+fn analysis_folder(matches: &ArgMatches) -> PathBuf {
+    if let Some(analysis_folder) = matches.get_one::<String>("analysis_folder") {
+        PathBuf::from(or_exit(
+            check_valid_folder_path(analysis_folder),
+            "Invalid path given for resulting CSV locations",
+        ))
+    } else {
+        or_exit(
+            current_dir(),
+            "Can't find the current folder to save result CSVs",
+        )
+    }
+}
+
+fn analysis_options(matches: &ArgMatches, get_hash: bool) -> AnalysisOptions {
+    let size_metric: SizeMetric = matches
+        .get_one::<String>("size_metric")
+        .expect("Size metric has a default")
+        .parse()
+        .expect("Clap only allows known size metrics");
+
+    AnalysisOptions {
+        get_hash,
+        size_metric,
+        treemap: matches.get_flag("treemap"),
+        html_report: matches.get_flag("html_report"),
+        format: result_format(matches),
+    }
+}
+
+/// Walks a folder and saves the cache, optionally analysing it right away.
+fn index(matches: &ArgMatches) {
     // Folder is required, so Clap will throw an error before this already.
-    let index_path = check_valid_folder_path(
-        matches
-            .get_one::<String>("index_path")
-            .expect("Failed to pass index path"),
-    )
-    .expect("Invalid path given.");
+    let index_path = or_exit(
+        check_valid_folder_path(
+            matches
+                .get_one::<String>("index_path")
+                .expect("Failed to pass index path"),
+        ),
+        "Invalid path given",
+    );
 
     // Deciding where to save the parquet cache for later analysis.
     let cache_path: PathBuf =
         if let Some(cache_location) = matches.get_one::<String>("cache_location") {
-            PathBuf::from(
-                check_valid_folder_path(&cache_location)
-                    .expect("Invalid path given for cache location."),
-            )
+            PathBuf::from(or_exit(
+                check_valid_folder_path(cache_location),
+                "Invalid path given for cache location",
+            ))
         } else {
-            current_dir().expect("Can't locate executable: cannot save cache.")
-        };
-
-    // Specifying analysis folder.
-    let analysis_folder: PathBuf =
-        if let Some(analysis_folder) = matches.get_one::<String>("analysis_folder") {
-            PathBuf::from(
-                check_valid_folder_path(&analysis_folder)
-                    .expect("Invalid path given for resulting CSV locations."),
+            or_exit(
+                current_dir(),
+                "Can't find the current folder to save the cache",
             )
-        } else {
-            current_dir().expect("Can't locate executable: cannot save result CSVs.")
         };
 
-    let get_metadata = matches.get_flag("metadata");
+    // Checked before indexing, so a wrong folder doesn't waste a whole walk.
+    let analysis_folder = matches
+        .get_flag("analysis")
+        .then(|| analysis_folder(matches));
+
     let get_hash = matches.get_flag("hash");
 
+    // Patterns to skip during the walk.
+    let patterns = |name: &str| -> Vec<String> {
+        matches
+            .get_many::<String>(name)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    let filter = or_exit(
+        PathFilter::new(
+            index_path,
+            &patterns("include"),
+            &patterns("exclude"),
+            matches.get_flag("ignore_files"),
+        ),
+        "Invalid include or exclude pattern",
+    );
+
+    // Clap already checks that -D, -i and -a come with -m.
+    let options = IndexOptions {
+        get_metadata: matches.get_flag("metadata"),
+        get_hash,
+        hash_algorithm: hash_algorithm(matches),
+        quick_hash: matches.get_flag("quick_hash").then(|| QuickHash {
+            block_size: matches
+                .get_one::<u64>("quick_hash_size")
+                .expect("Quick hash size has a default")
+                * 1024,
+            samples: *matches
+                .get_one::<u64>("quick_hash_samples")
+                .expect("Quick hash samples have a default"),
+        }),
+        hash_duplicates: matches.get_flag("duplicates"),
+        incremental: matches.get_flag("incremental"),
+        follow_symlinks: matches.get_flag("follow_symlinks"),
+        one_file_system: matches.get_flag("one_file_system"),
+        traversal: matches
+            .get_one::<String>("traversal")
+            .expect("Traversal has a default")
+            .parse()
+            .expect("Clap only allows known traversals"),
+        filter,
+    };
+
     // Running index and creating DataFrame.
-    let df = create_path_index(index_path, &cache_path, get_metadata, get_hash);
+    let cache_name = matches
+        .get_one::<String>("cache_name")
+        .cloned()
+        .unwrap_or_else(|| default_cache_name(index_path));
+    let cache_file = cache_file_path(&cache_path, &cache_name);
 
-    // Optional Polars analysis on the results.
-    if matches.get_flag("analysis") {
-        if matches.get_flag("metadata") {
-            run_analysis(df, analysis_folder.as_path(), get_hash);
-        } else {
-            warn!("Analysis requires metadata flag (-m).")
+    let errors = or_exit(
+        create_path_index(index_path, &cache_file, &options),
+        "Failed to index",
+    );
+
+    // Unreadable paths don't stop the walk, they are left out of the cache or left unhashed.
+    for error in &errors {
+        warn!("Failed to read {}", error);
+    }
+
+    // Optional Polars analysis on the results, which needs the whole index in memory.
+    if let Some(analysis_folder) = analysis_folder {
+        let df = or_exit(load_path_index_cache(&cache_file), "Failed to load cache");
+        or_exit(
+            run_analysis(
+                df,
+                analysis_folder.as_path(),
+                &analysis_options(matches, get_hash),
+            ),
+            "Failed to analyse",
+        );
+    }
+}
+
+/// Analyses an existing cache without walking the disk again.
+fn analyze(matches: &ArgMatches) {
+    let cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("from_cache")
+                .expect("Failed to pass cache"),
+        ),
+        "Invalid path given for cache",
+    );
+
+    let df = or_exit(load_path_index_cache(cache), "Failed to load cache");
+    let options = analysis_options(matches, matches.get_flag("hash"));
+
+    if let Err(e) = check_analysis_cache(&df, &options) {
+        error!("Can't analyse {:?}: {}", cache, e);
+        exit(1);
+    }
+
+    or_exit(
+        run_analysis(df, analysis_folder(matches).as_path(), &options),
+        "Failed to analyse",
+    );
+}
+
+/// Compares two existing caches without indexing anything.
+fn diff(matches: &ArgMatches) {
+    let old_cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("old_cache")
+                .expect("Failed to pass old cache"),
+        ),
+        "Invalid path given for old cache",
+    );
+    let new_cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("new_cache")
+                .expect("Failed to pass new cache"),
+        ),
+        "Invalid path given for new cache",
+    );
+
+    or_exit(
+        run_diff(
+            &or_exit(load_path_index_cache(old_cache), "Failed to load old cache"),
+            &or_exit(load_path_index_cache(new_cache), "Failed to load new cache"),
+            analysis_folder(matches).as_path(),
+            result_format(matches),
+        ),
+        "Failed to compare caches",
+    );
+}
+
+/// Opens the terminal browser over an existing cache.
+fn browse(matches: &ArgMatches) {
+    let cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("cache")
+                .expect("Failed to pass cache"),
+        ),
+        "Invalid path given for cache",
+    );
+
+    let df = or_exit(load_path_index_cache(cache), "Failed to load cache");
+    let folders = or_exit(folder_index(&df), "Failed to build folder index");
+    let tree = or_exit(FileTree::from_df(&df, &folders), "Failed to build tree");
+
+    or_exit(run_browser(tree), "Failed to run browser");
+}
+
+/// Prints the metadata saved in a cache.
+fn info(matches: &ArgMatches) {
+    let cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("cache")
+                .expect("Failed to pass cache"),
+        ),
+        "Invalid path given for cache",
+    );
+
+    let metadata = or_exit(load_cache_metadata(cache), "Failed to read cache metadata");
+    if metadata.is_empty() {
+        warn!(
+            "{:?} has no metadata: it was created by an older version.",
+            cache
+        );
+    }
+
+    for (key, value) in metadata {
+        println!("{}: {}", key, value);
+    }
+}
+
+/// Filters an existing cache, printing the matches or saving them to a CSV.
+fn query(matches: &ArgMatches) {
+    let cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("cache")
+                .expect("Failed to pass cache"),
+        ),
+        "Invalid path given for cache",
+    );
+
+    let is_folder = if matches.get_flag("files_only") {
+        Some(false)
+    } else if matches.get_flag("folders_only") {
+        Some(true)
+    } else {
+        None
+    };
+
+    let expressions = matches
+        .get_many::<String>("where")
+        .map(|values| {
+            values
+                .map(|value| {
+                    parse_where(value).unwrap_or_else(|e| {
+                        error!("Invalid --where expression '{}': {}", value, e);
+                        exit(2);
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let filter = QueryFilter {
+        path_prefix: matches.get_one::<String>("path_prefix").cloned(),
+        extensions: matches
+            .get_many::<String>("extension")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        min_size: matches.get_one::<u64>("min_size").copied(),
+        max_size: matches.get_one::<u64>("max_size").copied(),
+        is_folder,
+        expressions,
+    };
+
+    let df = or_exit(load_path_index_cache(cache), "Failed to load cache");
+    let mut df = or_exit(run_query(&df, &filter), "Failed to run query");
+
+    let format: OutputFormat = matches
+        .get_one::<String>("format")
+        .expect("Format has a default")
+        .parse()
+        .expect("Clap only allows known formats");
+
+    let result = if let Some(output) = matches.get_one::<String>("output") {
+        let file = or_exit(File::create(output), "Failed to create output file");
+        write_results(&mut df, format, &mut BufWriter::new(file))
+    } else {
+        write_results(&mut df, format, &mut BufWriter::new(stdout().lock()))
+    };
+    or_exit(result, "Failed to write query results");
+
+    info!("{} matching paths", df.height());
+}
+
+/// Runs a SQL query against the given caches, printing the results or saving them to a file.
+fn sql(matches: &ArgMatches) {
+    let tables: Vec<(String, DataFrame)> = matches
+        .get_many::<String>("cache")
+        .expect("Clap requires at least one cache")
+        .map(|cache| {
+            let (table_name, cache) = cache.split_once('=').unwrap_or(("files", cache));
+            let cache = or_exit(check_valid_file_path(cache), "Invalid path given for cache");
+            let df = or_exit(load_path_index_cache(cache), "Failed to load cache");
+            (table_name.to_string(), df)
+        })
+        .collect();
+
+    let query = matches
+        .get_one::<String>("query")
+        .expect("Failed to pass query");
+
+    let mut df = match run_sql(&tables, query) {
+        Ok(df) => df,
+        Err(e) => {
+            error!("Failed to run query: {}", e);
+            exit(1);
+        }
+    };
+
+    if let Some(output) = matches.get_one::<String>("output") {
+        let output = Path::new(output);
+        let file_name = or_exit(
+            output
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or("expected a file name"),
+            "Invalid output file name",
+        );
+        let folder = match output.parent() {
+            Some(folder) if !folder.as_os_str().is_empty() => folder.to_path_buf(),
+            _ => or_exit(
+                current_dir(),
+                "Can't find the current folder to save query results",
+            ),
+        };
+
+        or_exit(
+            print_and_save(
+                &mut df,
+                &folder,
+                file_name,
+                "Query results",
+                result_format(matches),
+            ),
+            "Failed to save query results",
+        );
+    } else {
+        println!("{}", df);
+    }
+}
+
+/// Prints hashes in the same format as `sha256sum`.
+/// Files that can't be read are reported, and the rest are still hashed.
+fn hash(matches: &ArgMatches) {
+    let algorithm = hash_algorithm(matches);
+    let mut failed = false;
+
+    for file in matches
+        .get_many::<String>("files")
+        .expect("Clap requires at least one file")
+    {
+        match check_valid_file_path(file).and_then(|path| hash_file(path, algorithm)) {
+            Ok(hash) => println!("{}  {}", hash, file),
+            Err(e) => {
+                error!("Failed to hash {}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+}
+
+/// Writes the hashes of a cache as a manifest relative to the indexed folder.
+fn manifest(matches: &ArgMatches) {
+    let cache = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("cache")
+                .expect("Failed to pass cache"),
+        ),
+        "Invalid path given for cache",
+    );
+
+    let df = or_exit(load_path_index_cache(cache), "Failed to load cache");
+    let algorithm = match cache_hash_algorithm(&df) {
+        Ok(algorithm) => algorithm,
+        Err(e) => {
+            error!("Can't create a manifest from {:?}: {}", cache, e);
+            exit(1);
         }
+    };
+    let root = PathBuf::from(or_exit(
+        index_root(&df),
+        "Failed to find the indexed folder",
+    ));
+    let entries = or_exit(
+        manifest_entries(&df, &root),
+        "Failed to read hashes from cache",
+    );
+
+    let result = if let Some(output) = matches.get_one::<String>("output") {
+        let file = or_exit(File::create(output), "Failed to create output file");
+        write_manifest(&entries, &mut BufWriter::new(file))
+    } else {
+        write_manifest(&entries, &mut BufWriter::new(stdout().lock()))
+    };
+    or_exit(result, "Failed to write manifest");
+
+    info!(
+        "{} {} hashes, relative to {:?}",
+        entries.len(),
+        algorithm,
+        root
+    );
+}
+
+/// Re-hashes the files in a cache or manifest, exiting with an error code if anything changed.
+fn verify(matches: &ArgMatches) {
+    let source = or_exit(
+        check_valid_file_path(
+            matches
+                .get_one::<String>("source")
+                .expect("Failed to pass cache or manifest"),
+        ),
+        "Invalid path given for cache or manifest",
+    );
+
+    let report = if source
+        .extension()
+        .is_some_and(|extension| extension == "parquet")
+    {
+        let df = or_exit(load_path_index_cache(source), "Failed to load cache");
+        or_exit(verify_cache(&df, source), "Failed to verify cache")
+    } else {
+        let file = or_exit(File::open(source), "Failed to open manifest");
+        let entries = match read_manifest(BufReader::new(file)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("{}", e);
+                exit(2);
+            }
+        };
+
+        let algorithm = match matches.get_one::<String>("hash_algorithm") {
+            Some(algorithm) => algorithm
+                .parse()
+                .expect("Clap only allows known hash algorithms"),
+            None => guess_hash_algorithm(&entries).unwrap_or_else(|| {
+                error!(
+                    "Can't tell the hash algorithm of {:?}, pass it with -a.",
+                    source
+                );
+                exit(2);
+            }),
+        };
+
+        let root = match matches.get_one::<String>("root") {
+            Some(root) => PathBuf::from(root),
+            // A bare file name has an empty parent, which is the current folder.
+            None => source
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map_or(PathBuf::from("."), Path::to_path_buf),
+        };
+
+        or_exit(
+            verify_manifest(&entries, &root, algorithm, source),
+            "Failed to verify manifest",
+        )
+    };
+
+    for path in &report.changed {
+        warn!("Changed: {}", path);
+    }
+    for path in &report.missing {
+        warn!("Missing: {}", path);
+    }
+    for path in &report.extra {
+        warn!("Extra: {}", path);
+    }
+    info!(
+        "{} files verified, {} changed, {} missing, {} extra",
+        report.verified,
+        report.changed.len(),
+        report.missing.len(),
+        report.extra.len()
+    );
+
+    if !report.is_ok() {
+        exit(1);
     }
 }
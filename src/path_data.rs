@@ -2,6 +2,9 @@ use std::{path::PathBuf, time::SystemTime};
 
 use serde::{Deserialize, Serialize};
 
+use crate::utils::hashing::HashAlgorithm;
+
+#[allow(clippy::module_inception)]
 pub mod path_data;
 
 /// Data collected per path.
@@ -13,9 +16,25 @@ pub struct PathData {
     pub name: String,
     pub stem: Option<String>,
     pub size: Option<u64>,
+    pub allocated_size: Option<u64>,
     pub extension: Option<String>,
     pub created: Option<SystemTime>,
     pub modified: Option<SystemTime>,
     pub is_folder: bool,
     pub hash: Option<String>,
+    pub hash_algorithm: Option<HashAlgorithm>,
+    pub file_type: FileType,
+    pub link_target: Option<PathBuf>,
+    pub device: Option<u64>,
+    pub inode: Option<u64>,
+    pub link_count: Option<u64>,
+}
+
+/// Kind of filesystem entry. Symlinks are recorded as links, even when they are followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileType {
+    File,
+    Folder,
+    Symlink,
+    Other,
 }
@@ -1,20 +1,29 @@
 use std::{path::PathBuf, time::SystemTime};
 
-use crate::path_data::PathData;
+use crate::path_data::{FileType, PathData};
+use crate::utils::hashing::HashAlgorithm;
 
 impl PathData {
     /// Creating a new DataFrame instance.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         path: PathBuf,
         parent: PathBuf,
         name: String,
         stem: Option<String>,
         size: Option<u64>,
+        allocated_size: Option<u64>,
         extension: Option<String>,
         created: Option<SystemTime>,
         modified: Option<SystemTime>,
         is_folder: bool,
         hash: Option<String>,
+        hash_algorithm: Option<HashAlgorithm>,
+        file_type: FileType,
+        link_target: Option<PathBuf>,
+        device: Option<u64>,
+        inode: Option<u64>,
+        link_count: Option<u64>,
     ) -> Self {
         PathData {
             path,
@@ -22,11 +31,57 @@ impl PathData {
             name,
             stem,
             size,
+            allocated_size,
             extension,
             created,
             modified,
             is_folder,
             hash,
+            hash_algorithm,
+            file_type,
+            link_target,
+            device,
+            inode,
+            link_count,
+        }
+    }
+}
+
+impl FileType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileType::File => "file",
+            FileType::Folder => "folder",
+            FileType::Symlink => "symlink",
+            FileType::Other => "other",
+        }
+    }
+}
+
+impl std::str::FromStr for FileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(FileType::File),
+            "folder" => Ok(FileType::Folder),
+            "symlink" => Ok(FileType::Symlink),
+            "other" => Ok(FileType::Other),
+            _ => Err(format!("Unknown file type: {}", s)),
+        }
+    }
+}
+
+impl From<std::fs::FileType> for FileType {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_dir() {
+            FileType::Folder
+        } else if file_type.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
         }
     }
 }
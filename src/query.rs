@@ -0,0 +1,4 @@
+pub mod expression;
+pub mod filter;
+pub mod output;
+pub mod sql;
@@ -0,0 +1,631 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+
+/// Characters that make up comparison operators.
+const OPERATOR_CHARS: &str = "=!<>^~";
+
+/// Units for ages such as `90d`, in seconds.
+const AGE_UNITS: [(&str, i64); 6] = [
+    ("s", 1),
+    ("min", 60),
+    ("h", 60 * 60),
+    ("d", 24 * 60 * 60),
+    ("w", 7 * 24 * 60 * 60),
+    ("y", 365 * 24 * 60 * 60),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// Quoted text, which is never read as a keyword.
+    Quoted(String),
+    Operator(String),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        // Values are only split at whitespace and parentheses, so globs like `[!a]*` stay whole.
+        let is_value = matches!(tokens.last(), Some(Token::Operator(_)));
+
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push(Token::Open);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(Token::Close);
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => break,
+                    Some(next) => text.push(next),
+                    None => return Err(format!("missing closing quote in '{}'", input)),
+                }
+            }
+            tokens.push(Token::Quoted(text));
+        } else if OPERATOR_CHARS.contains(c) && !is_value {
+            let mut operator = String::new();
+            while let Some(&next) = chars.peek().filter(|next| OPERATOR_CHARS.contains(**next)) {
+                operator.push(next);
+                chars.next();
+            }
+            tokens.push(Token::Operator(operator));
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek().filter(|next| {
+                !next.is_whitespace()
+                    && !"()".contains(**next)
+                    && (is_value || !OPERATOR_CHARS.contains(**next))
+            }) {
+                word.push(next);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    StartsWith,
+    Glob,
+}
+
+impl std::str::FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "=" | "==" => Ok(Operator::Eq),
+            "!=" => Ok(Operator::NotEq),
+            "<" => Ok(Operator::Lt),
+            "<=" => Ok(Operator::LtEq),
+            ">" => Ok(Operator::Gt),
+            ">=" => Ok(Operator::GtEq),
+            "^=" => Ok(Operator::StartsWith),
+            "~" => Ok(Operator::Glob),
+            _ => Err(format!("unknown operator '{}'", s)),
+        }
+    }
+}
+
+/// How values of a field are parsed and compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Size,
+    Time,
+    Text,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Size,
+    AllocatedSize,
+    Extension,
+    Name,
+    Path,
+    Parent,
+    Modified,
+    Created,
+    IsFolder,
+    FileType,
+    Hash,
+}
+
+impl Field {
+    fn column(&self) -> &'static str {
+        match self {
+            Field::Size => "size",
+            Field::AllocatedSize => "allocated_size",
+            Field::Extension => "extension",
+            Field::Name => "name",
+            Field::Path => "path",
+            Field::Parent => "parents",
+            Field::Modified => "modified",
+            Field::Created => "created",
+            Field::IsFolder => "is_folder",
+            Field::FileType => "file_type",
+            Field::Hash => "hash",
+        }
+    }
+
+    fn kind(&self) -> FieldKind {
+        match self {
+            Field::Size | Field::AllocatedSize => FieldKind::Size,
+            Field::Modified | Field::Created => FieldKind::Time,
+            Field::IsFolder => FieldKind::Bool,
+            _ => FieldKind::Text,
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "size" => Ok(Field::Size),
+            "allocated" | "allocated_size" => Ok(Field::AllocatedSize),
+            "ext" | "extension" => Ok(Field::Extension),
+            "name" => Ok(Field::Name),
+            "path" => Ok(Field::Path),
+            "parent" | "parents" => Ok(Field::Parent),
+            "mtime" | "modified" => Ok(Field::Modified),
+            "ctime" | "created" => Ok(Field::Created),
+            "is_folder" | "folder" => Ok(Field::IsFolder),
+            "type" | "file_type" => Ok(Field::FileType),
+            "hash" => Ok(Field::Hash),
+            _ => Err(format!(
+                "unknown field '{}': use size, allocated, ext, name, path, parent, mtime, ctime, is_folder, type or hash",
+                s
+            )),
+        }
+    }
+}
+
+/// Size in bytes, e.g. `1500`, `10K` or `1.5GB`. K, M, G and T (or KiB, MiB, ...) are powers of
+/// 1024, like `du`; KB, MB, GB and TB are powers of 1000.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("unknown size unit '{}' in '{}'", unit, value)),
+    };
+
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Unix timestamp of a local date (`2024-01-31`), date and time (`2024-01-31T12:00:00`),
+/// or an age such as `90d`, which means 90 days ago.
+fn parse_time(value: &str) -> Result<i64, String> {
+    let age = AGE_UNITS.iter().find_map(|(unit, seconds)| {
+        value
+            .strip_suffix(unit)?
+            .parse::<i64>()
+            .ok()
+            .map(|count| count * seconds)
+    });
+    if let Some(age) = age {
+        return Ok(Local::now().timestamp() - age);
+    }
+
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).expect("Midnight is a valid time"))
+        })
+        .ok()
+        .and_then(|datetime| datetime.and_local_timezone(Local).earliest())
+        .map(|datetime| datetime.timestamp())
+        .ok_or_else(|| {
+            format!(
+                "invalid time '{}': use a date like 2024-01-31, or an age like 90d",
+                value
+            )
+        })
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("invalid boolean '{}': use true or false", value)),
+    }
+}
+
+/// Translates a glob into an anchored regex: `*` matches anything (including `/`), `?` a single
+/// character, and `[...]` or `[!...]` a character class.
+fn glob_to_regex(glob: &str) -> Result<String, String> {
+    let mut regex = String::from("^");
+    let mut in_class = false;
+
+    for c in glob.chars() {
+        match c {
+            '[' if !in_class => {
+                in_class = true;
+                regex.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(']');
+            }
+            '!' if in_class && regex.ends_with('[') => regex.push('^'),
+            _ if in_class => {
+                if "\\[&~".contains(c) {
+                    regex.push('\\');
+                }
+                regex.push(c);
+            }
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => {
+                if "\\.+()|{}^$#&-~".contains(c) {
+                    regex.push('\\');
+                }
+                regex.push(c);
+            }
+        }
+    }
+
+    if in_class {
+        return Err(format!("missing ']' in glob '{}'", glob));
+    }
+    regex.push('$');
+
+    Ok(regex)
+}
+
+fn compare(expr: Expr, operator: Operator, value: Expr) -> Expr {
+    match operator {
+        Operator::Eq => expr.eq(value),
+        Operator::NotEq => expr.neq(value),
+        Operator::Lt => expr.lt(value),
+        Operator::LtEq => expr.lt_eq(value),
+        Operator::Gt => expr.gt(value),
+        Operator::GtEq => expr.gt_eq(value),
+        Operator::StartsWith | Operator::Glob => unreachable!("Only used for text fields"),
+    }
+}
+
+/// Compiles a single `field operator value` comparison.
+fn comparison(field: Field, operator: Operator, value: &str, quoted: bool) -> Result<Expr, String> {
+    let column = col(field.column());
+
+    // Unquoted null checks whether a field is missing, e.g. files indexed without metadata or hash.
+    if !quoted && value.eq_ignore_ascii_case("null") {
+        return match operator {
+            Operator::Eq => Ok(column.is_null()),
+            Operator::NotEq => Ok(column.is_not_null()),
+            _ => Err("null can only be compared with = or !=".to_string()),
+        };
+    }
+
+    let is_ordering = !matches!(operator, Operator::StartsWith | Operator::Glob);
+    let is_equality = matches!(operator, Operator::Eq | Operator::NotEq);
+
+    match field.kind() {
+        FieldKind::Size if is_ordering => Ok(compare(column, operator, lit(parse_size(value)?))),
+        FieldKind::Time if is_ordering => Ok(compare(column, operator, lit(parse_time(value)?))),
+        FieldKind::Bool if is_equality => Ok(compare(column, operator, lit(parse_bool(value)?))),
+        FieldKind::Text => {
+            // Extensions and hashes are compared case-insensitively.
+            let (column, value) = match field {
+                Field::Extension => (
+                    column.str().to_lowercase(),
+                    value.trim_start_matches('.').to_lowercase(),
+                ),
+                Field::Hash => (column, value.to_lowercase()),
+                _ => (column, value.to_string()),
+            };
+
+            match operator {
+                Operator::Eq => Ok(column.eq_missing(lit(value))),
+                Operator::NotEq => Ok(column.neq_missing(lit(value))),
+                Operator::StartsWith => Ok(column.str().starts_with(lit(value))),
+                Operator::Glob => Ok(column.str().contains(lit(glob_to_regex(&value)?), true)),
+                _ => Err(format!(
+                    "{} can only be compared with =, !=, ^= or ~",
+                    field.column()
+                )),
+            }
+        }
+        FieldKind::Bool => Err(format!(
+            "{} can only be compared with = or !=",
+            field.column()
+        )),
+        _ => Err(format!(
+            "{} can only be compared with =, !=, <, <=, > or >=",
+            field.column()
+        )),
+    }
+}
+
+/// Recursive descent parser: `or` binds weaker than `and`, which binds weaker than `not`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes an unquoted keyword such as `and`, if it is next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("or") {
+            expr = expr.or(self.parse_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.keyword("and") {
+            expr = expr.and(self.parse_unary()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(self.parse_unary()?.not());
+        }
+
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let expr = self.parse_or()?;
+            return match self.next() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err("missing ')'".to_string()),
+            };
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let field: Field = match self.next() {
+            Some(Token::Word(word)) => word.parse()?,
+            Some(token) => return Err(format!("expected a field, found {}", describe(&token))),
+            None => return Err("expected a field, found the end of the expression".to_string()),
+        };
+
+        let operator: Operator = match self.peek() {
+            Some(Token::Operator(operator)) => {
+                let operator = operator.parse()?;
+                self.position += 1;
+                operator
+            }
+            // A bare boolean field, e.g. `not is_folder`.
+            _ if field.kind() == FieldKind::Bool => return Ok(col(field.column())),
+            _ => return Err(format!("expected an operator after {}", field.column())),
+        };
+
+        match self.next() {
+            Some(Token::Word(value)) => comparison(field, operator, &value, false),
+            Some(Token::Quoted(value)) => comparison(field, operator, &value, true),
+            _ => Err(format!("expected a value after {}", field.column())),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Quoted(text) => format!("\"{}\"", text),
+        Token::Operator(operator) => format!("'{}'", operator),
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+    }
+}
+
+/// Compiles a filter expression, e.g. `ext = log and size > 1G and mtime < 90d and path ^= /data`,
+/// into a Polars expression over the path index.
+pub fn parse_where(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {}", describe(token))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn index() -> DataFrame {
+        let now = Local::now().timestamp();
+
+        df!(
+            "path" => ["/data/logs", "/data/logs/a.log", "/data/logs/b.LOG", "/data/a~b", "/data/big.iso", "/data/empty"],
+            "name" => ["logs", "a.log", "b.LOG", "a~b", "big.iso", "empty"],
+            "parents" => ["/data", "/data/logs", "/data/logs", "/data", "/data", "/data"],
+            "extension" => [None, Some("log"), Some("LOG"), None, Some("iso"), None],
+            "size" => [4096u64, 100, 2000, 10, 1_050_000_000, 0],
+            "modified" => [now, now - 400 * DAY, now, now, now - 10 * DAY, now],
+            "is_folder" => [true, false, false, false, false, false],
+            "hash" => [None, Some("ab12"), Some("cd34"), None, Some("ef56"), Some("null")]
+        )
+        .unwrap()
+    }
+
+    /// Names of the entries matching the expression.
+    fn matching(expression: &str) -> Vec<String> {
+        let df = index()
+            .lazy()
+            .filter(parse_where(expression).unwrap())
+            .collect()
+            .unwrap();
+
+        df.column("name")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn globs_become_anchored_regexes() {
+        assert_eq!(glob_to_regex("*.log").unwrap(), r"^.*\.log$");
+        assert_eq!(glob_to_regex("file?.txt").unwrap(), r"^file.\.txt$");
+        assert_eq!(glob_to_regex("[!ab]*").unwrap(), "^[^ab].*$");
+        assert_eq!(glob_to_regex("a!b~c").unwrap(), r"^a!b\~c$");
+        assert_eq!(glob_to_regex("(a+b)").unwrap(), r"^\(a\+b\)$");
+        assert!(glob_to_regex("[abc").is_err());
+    }
+
+    #[test]
+    fn size_units() {
+        assert_eq!(parse_size("1500").unwrap(), 1500);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1GB").unwrap(), 1_000_000_000);
+        assert_eq!(parse_size("1.5k").unwrap(), 1536);
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("G").is_err());
+    }
+
+    #[test]
+    fn binary_and_decimal_sizes_differ() {
+        assert_eq!(matching("size > 1GB"), ["big.iso"]);
+        assert!(matching("size > 1G").is_empty());
+    }
+
+    #[test]
+    fn bare_boolean_fields() {
+        assert_eq!(matching("is_folder"), ["logs"]);
+        assert_eq!(
+            matching("not is_folder"),
+            ["a.log", "b.LOG", "a~b", "big.iso", "empty"]
+        );
+        assert_eq!(
+            matching("folder = false and size > 1000"),
+            ["b.LOG", "big.iso"]
+        );
+    }
+
+    #[test]
+    fn null_comparisons() {
+        assert_eq!(matching("hash = null"), ["logs", "a~b"]);
+        assert_eq!(
+            matching("hash != NULL"),
+            ["a.log", "b.LOG", "big.iso", "empty"]
+        );
+        // Quoted, null is just text.
+        assert_eq!(matching("hash = 'null'"), ["empty"]);
+        assert!(parse_where("size > null").is_err());
+    }
+
+    #[test]
+    fn text_comparisons() {
+        assert_eq!(matching("ext = .log"), ["a.log", "b.LOG"]);
+        assert_eq!(matching("hash = AB12"), ["a.log"]);
+        assert_eq!(
+            matching("ext != log and not is_folder"),
+            ["a~b", "big.iso", "empty"]
+        );
+        assert_eq!(matching("path ^= /data/logs/"), ["a.log", "b.LOG"]);
+        assert_eq!(matching("name = \"a~b\""), ["a~b"]);
+    }
+
+    #[test]
+    fn unquoted_globs_keep_operator_characters() {
+        assert_eq!(matching("name ~ [!ab]*"), ["logs", "empty"]);
+        assert_eq!(matching("name ~ a~*"), ["a~b"]);
+        assert_eq!(matching("name~*.LOG"), ["b.LOG"]);
+        assert_eq!(matching("path ~ */logs/*"), ["a.log", "b.LOG"]);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(matching("mtime < 1y"), ["a.log"]);
+        assert_eq!(
+            matching("mtime < 7d and not is_folder"),
+            ["a.log", "big.iso"]
+        );
+        assert!(parse_time("2024-01-31").is_ok());
+        assert!(parse_time("2024-01-31T12:00:00").is_ok());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        // `and` binds tighter than `or`.
+        assert_eq!(
+            matching("name = empty or ext = log and size > 1000"),
+            ["b.LOG", "empty"]
+        );
+        assert_eq!(
+            matching("(name = empty or ext = log) and size > 1000"),
+            ["b.LOG"]
+        );
+        // `not` only applies to the comparison right after it.
+        assert_eq!(matching("not ext = log and size < 100"), ["a~b", "empty"]);
+        assert_eq!(
+            matching("not (ext = log or size < 100)"),
+            ["logs", "big.iso"]
+        );
+        assert_eq!(matching("NOT is_folder AND size = 0"), ["empty"]);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for expression in [
+            "",
+            "size",
+            "size >",
+            "size ~ 1G",
+            "ext > log",
+            "is_folder < true",
+            "owner = me",
+            "size >> 1",
+            "(size > 1",
+            "size > 1)",
+            "name = 'open",
+            "name = a name = b",
+        ] {
+            assert!(parse_where(expression).is_err(), "{}", expression);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use polars::prelude::*;
+
+/// Filters over a cached index. All filters that are set must match.
+#[derive(Debug, Clone, Default)]
+pub struct QueryFilter {
+    /// Only paths starting with this prefix.
+    pub path_prefix: Option<String>,
+    /// Only files with one of these extensions, without the leading dot.
+    pub extensions: Vec<String>,
+    /// Minimum size in bytes.
+    pub min_size: Option<u64>,
+    /// Maximum size in bytes.
+    pub max_size: Option<u64>,
+    /// Only files, or only folders. Both by default.
+    pub is_folder: Option<bool>,
+    /// Compiled `--where` expressions, see `parse_where`.
+    pub expressions: Vec<Expr>,
+}
+
+impl QueryFilter {
+    /// Combines the filters into a single Polars expression.
+    pub fn to_expr(&self) -> Expr {
+        let mut filters = Vec::new();
+
+        if let Some(path_prefix) = &self.path_prefix {
+            filters.push(col("path").str().starts_with(lit(path_prefix.as_str())));
+        }
+
+        if let Some(extension_filter) = self
+            .extensions
+            .iter()
+            .map(|extension| col("extension").eq(lit(extension.trim_start_matches('.'))))
+            .reduce(|a, b| a.or(b))
+        {
+            filters.push(extension_filter);
+        }
+
+        if let Some(min_size) = self.min_size {
+            filters.push(col("size").gt_eq(lit(min_size)));
+        }
+
+        if let Some(max_size) = self.max_size {
+            filters.push(col("size").lt_eq(lit(max_size)));
+        }
+
+        if let Some(is_folder) = self.is_folder {
+            filters.push(col("is_folder").eq(lit(is_folder)));
+        }
+
+        filters.extend(self.expressions.iter().cloned());
+
+        filters
+            .into_iter()
+            .reduce(|a, b| a.and(b))
+            .unwrap_or(lit(true))
+    }
+}
+
+/// All paths in the index matching the filter, sorted by path.
+pub fn run_query(df: &DataFrame, filter: &QueryFilter) -> Result<DataFrame, PolarsError> {
+    df.clone()
+        .lazy()
+        .filter(filter.to_expr())
+        .sort(["path"], SortMultipleOptions::default())
+        .collect()
+}
@@ -0,0 +1,76 @@
+use polars::prelude::*;
+
+use std::io::Write;
+
+/// Columns shown in the table output; the other formats include every column.
+const TABLE_COLUMNS: [&str; 5] = ["path", "size", "modified", "file_type", "hash"];
+
+/// How query results are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable table.
+    #[default]
+    Table,
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+    /// One path per line.
+    Paths,
+    /// NUL-separated paths, for `xargs -0`.
+    Paths0,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "paths" => Ok(OutputFormat::Paths),
+            "paths0" => Ok(OutputFormat::Paths0),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Writes the query results in the given format.
+pub fn write_results<W: Write>(
+    df: &mut DataFrame,
+    format: OutputFormat,
+    writer: &mut W,
+) -> PolarsResult<()> {
+    match format {
+        OutputFormat::Table => {
+            let table = df
+                .clone()
+                .lazy()
+                .select(TABLE_COLUMNS.map(col))
+                // Timestamps are stored as seconds, which are hard to read.
+                .with_column(
+                    (col("modified") * lit(1000))
+                        .cast(DataType::Datetime(TimeUnit::Milliseconds, None)),
+                )
+                .collect()?;
+            writeln!(writer, "{}", table)?;
+        }
+        OutputFormat::Csv => CsvWriter::new(&mut *writer).finish(df)?,
+        OutputFormat::Ndjson => JsonWriter::new(&mut *writer)
+            .with_json_format(JsonFormat::JsonLines)
+            .finish(df)?,
+        OutputFormat::Paths | OutputFormat::Paths0 => {
+            let separator = if format == OutputFormat::Paths0 {
+                '\0'
+            } else {
+                '\n'
+            };
+            for path in df.column("path")?.str()?.into_iter().flatten() {
+                write!(writer, "{}{}", path, separator)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
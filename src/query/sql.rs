@@ -0,0 +1,37 @@
+use polars::prelude::*;
+use polars::sql::SQLContext;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use crate::indexing::folder_index::folder_index;
+
+/// Name of the folder table derived from a cache table: `files` gets `folders`, other tables
+/// get a `_folders` suffix, e.g. `old_folders`.
+pub fn folder_table_name(table_name: &str) -> String {
+    if table_name == "files" {
+        "folders".to_string()
+    } else {
+        format!("{}_folders", table_name)
+    }
+}
+
+/// Registers every cache as a table, along with a table of its cumulative folder sizes.
+pub fn sql_context(tables: &[(String, DataFrame)]) -> PolarsResult<SQLContext> {
+    let mut context = SQLContext::new();
+
+    for (table_name, df) in tables {
+        let folder_table = folder_table_name(table_name);
+        info!("Registering tables {} and {}", table_name, folder_table);
+
+        context.register(table_name, df.clone().lazy());
+        context.register(&folder_table, folder_index(df)?.lazy());
+    }
+
+    Ok(context)
+}
+
+/// Runs a SQL query against the caches.
+pub fn run_sql(tables: &[(String, DataFrame)], query: &str) -> PolarsResult<DataFrame> {
+    sql_context(tables)?.execute(query)?.collect()
+}
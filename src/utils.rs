@@ -1,2 +1,5 @@
 pub mod file_operations;
+pub mod formatting;
 pub mod hashing;
+pub mod manifest;
+pub mod verification;
@@ -3,45 +3,186 @@ use polars::prelude::*;
 #[allow(unused)]
 use log::{error, info, warn};
 
+use polars::io::parquet::write::BatchedWriter;
+use polars_parquet::read::{infer_schema, read_metadata, FileReader};
+use polars_parquet::write::KeyValue;
+
 use std::{
-    fs::create_dir_all,
-    io::{Error, ErrorKind},
-    path::Path,
+    fs::{create_dir_all, File},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
 };
 
-/// Saving the parquet cache.
-pub fn save_path_index_cache(file_path: &Path, df: &DataFrame) {
-    let cache_file_path = file_path.join("rust-file-index.parquet");
+use crate::error::{Error, Result};
+use crate::indexing::cache_metadata::{CacheMetadata, METADATA_KEY_PREFIX};
+use crate::utils::hashing::{hash_iterable, HashAlgorithm};
+
+/// Prefix of the cache names derived from the indexed folder.
+pub const INDEX_CACHE_PREFIX: &str = "rust-file-index";
+
+/// Suffix of the folder-level index saved next to each cache.
+const FOLDER_INDEX_SUFFIX: &str = "-folders";
 
-    info!("Saving cache: {:?}", cache_file_path);
+/// Cache name for an indexed folder, e.g. `rust-file-index-photos-1a2b3c4d`. The folder name keeps
+/// it recognisable, and the hash of the full path keeps folders with the same name apart.
+pub fn default_cache_name(index_path: &Path) -> String {
+    let full_path = index_path
+        .canonicalize()
+        .unwrap_or_else(|_| index_path.to_path_buf());
 
-    let mut file =
-        std::fs::File::create(cache_file_path).expect("Failed to create parquet index file");
+    let folder_name: String = full_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string())
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let path_hash = hash_iterable([full_path.to_string_lossy().as_bytes()]);
+
+    format!("{}-{}-{}", INDEX_CACHE_PREFIX, folder_name, &path_hash[..8])
+}
 
-    ParquetWriter::new(&mut file)
-        .finish(&mut df.clone())
-        .unwrap();
+/// Location of the named parquet cache inside a cache folder.
+pub fn cache_file_path(cache_folder: &Path, cache_name: &str) -> PathBuf {
+    let cache_name = cache_name.strip_suffix(".parquet").unwrap_or(cache_name);
+    cache_folder.join(format!("{}.parquet", cache_name))
 }
 
-/// Loading the cache: currently not used.
-pub fn _load_path_index_cache(file_path: &Path) -> DataFrame {
-    let mut file = std::fs::File::open(file_path).expect("Failed to open file");
-    ParquetReader::new(&mut file).finish().unwrap()
+/// Location of the cumulative folder sizes saved next to a cache, e.g. `name-folders.parquet`.
+pub fn folder_index_file_path(cache_file: &Path) -> PathBuf {
+    let stem = cache_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    cache_file.with_file_name(format!("{}{}.parquet", stem, FOLDER_INDEX_SUFFIX))
+}
+
+/// Writes the parquet footer, with the key-value pairs in the file metadata.
+pub fn finish_parquet<W: Write>(
+    writer: BatchedWriter<W>,
+    metadata: &[(String, String)],
+) -> PolarsResult<()> {
+    let key_values = metadata
+        .iter()
+        .map(|(key, value)| KeyValue {
+            key: key.clone(),
+            value: Some(value.clone()),
+        })
+        .collect();
+
+    // The batched writer gives access to the file footer, which `ParquetWriter::finish` doesn't.
+    writer
+        .get_writer()
+        .lock()
+        .expect("Parquet writer is not shared")
+        .end(Some(key_values))?;
+
+    Ok(())
+}
+
+/// Writes a DataFrame to a parquet file, with the key-value pairs in the file metadata.
+fn save_parquet(file_path: &Path, df: &DataFrame, metadata: &[(String, String)]) -> Result<()> {
+    info!("Saving cache: {:?}", file_path);
+
+    let file = File::create(file_path).map_err(|e| Error::io(file_path, e))?;
+
+    let mut writer = ParquetWriter::new(file).batched(&df.schema())?;
+    writer.write_batch(df.clone().as_single_chunk_par())?;
+    finish_parquet(writer, metadata)?;
+
+    Ok(())
+}
+
+/// Saving the folder-level index next to the parquet cache.
+pub fn save_folder_index_cache(
+    cache_file: &Path,
+    df: &DataFrame,
+    metadata: &CacheMetadata,
+) -> Result<()> {
+    save_parquet(
+        &folder_index_file_path(cache_file),
+        df,
+        &metadata.key_values(),
+    )
+}
+
+/// Loading the cache.
+/// Caches from before the hash algorithm could be chosen get a `hash_algorithm` column,
+/// as their hashes are all SHA256.
+pub fn load_path_index_cache(file_path: &Path) -> Result<DataFrame> {
+    let mut file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let mut df = ParquetReader::new(&mut file).finish()?;
+
+    if df.column("hash_algorithm").is_err() {
+        df = df
+            .lazy()
+            .with_column(
+                when(col("hash").is_not_null())
+                    .then(lit(HashAlgorithm::Sha256.as_str()))
+                    .otherwise(lit(NULL).cast(DataType::String))
+                    .alias("hash_algorithm"),
+            )
+            .collect()?;
+    }
+
+    Ok(df)
+}
+
+/// Reads a parquet file one row group at a time, so it never has to fit in memory as a whole.
+/// With `columns`, only those columns are read.
+pub fn read_parquet_batches(
+    file_path: &Path,
+    columns: Option<&[&str]>,
+) -> Result<impl Iterator<Item = PolarsResult<DataFrame>>> {
+    let mut file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let metadata = read_metadata(&mut file)?;
+
+    let mut schema = infer_schema(&metadata)?;
+    if let Some(columns) = columns {
+        schema = schema.filter(|_, field| columns.contains(&field.name.as_str()));
+    }
+    let fields = schema.fields.clone();
+
+    let reader = FileReader::new(file, metadata.row_groups, schema, None, None, None);
+    Ok(reader.map(move |batch| DataFrame::try_from((batch?, fields.as_slice()))))
+}
+
+/// Metadata written by this tool into a cache, as key-value pairs without the key prefix.
+/// Caches from older versions have none.
+pub fn load_cache_metadata(file_path: &Path) -> Result<Vec<(String, String)>> {
+    let mut file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let metadata = read_metadata(&mut file)?;
+
+    Ok(metadata
+        .key_value_metadata()
+        .iter()
+        .flatten()
+        .filter_map(|key_value| {
+            let key = key_value.key.strip_prefix(METADATA_KEY_PREFIX)?;
+            Some((key.to_string(), key_value.value.clone().unwrap_or_default()))
+        })
+        .collect())
 }
 
 /// Checks whether a path exists and whether it is a folder.
-pub fn check_valid_folder_path(path: &str) -> Result<&Path, Error> {
+pub fn check_valid_folder_path(path: &str) -> io::Result<&Path> {
     let path = Path::new(path);
 
     if !path.exists() {
-        return Err(Error::new(
+        return Err(io::Error::new(
             ErrorKind::NotFound,
             format!("The specified path does not exist: {:?}", path),
         ));
     }
 
     if !path.is_dir() {
-        return Err(Error::new(
+        return Err(io::Error::new(
             ErrorKind::InvalidInput,
             format!("The specified path is not a folder: {:?}", path),
         ));
@@ -50,22 +191,155 @@ pub fn check_valid_folder_path(path: &str) -> Result<&Path, Error> {
     Ok(path)
 }
 
+/// Checks whether a path exists and whether it is a file.
+pub fn check_valid_file_path(path: &str) -> io::Result<&Path> {
+    let path = Path::new(path);
+
+    if !path.exists() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("The specified path does not exist: {:?}", path),
+        ));
+    }
+
+    if !path.is_file() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("The specified path is not a file: {:?}", path),
+        ));
+    }
+
+    Ok(path)
+}
+
+/// File formats the analysis results can be saved in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    #[default]
+    Csv,
+    Tsv,
+    /// A JSON array with one object per row.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    Parquet,
+    /// A Markdown table, to paste into wiki pages.
+    Markdown,
+}
+
+impl ResultFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Csv => "csv",
+            ResultFormat::Tsv => "tsv",
+            ResultFormat::Json => "json",
+            ResultFormat::Ndjson => "ndjson",
+            ResultFormat::Parquet => "parquet",
+            ResultFormat::Markdown => "md",
+        }
+    }
+
+    /// File name for a result, e.g. `duplicates.csv`.
+    pub fn file_name(&self, stem: &str) -> String {
+        format!("{}.{}", stem, self.extension())
+    }
+}
+
+impl std::str::FromStr for ResultFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ResultFormat::Csv),
+            "tsv" => Ok(ResultFormat::Tsv),
+            "json" => Ok(ResultFormat::Json),
+            "ndjson" => Ok(ResultFormat::Ndjson),
+            "parquet" => Ok(ResultFormat::Parquet),
+            "markdown" => Ok(ResultFormat::Markdown),
+            _ => Err(format!("Unknown result format: {}", s)),
+        }
+    }
+}
+
+fn markdown_cell(value: AnyValue) -> String {
+    let text = match value {
+        AnyValue::Null => String::new(),
+        AnyValue::String(value) => value.to_string(),
+        value => value.to_string(),
+    };
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Writes a DataFrame as a Markdown table, with numbers aligned to the right.
+fn write_markdown<W: Write>(df: &DataFrame, writer: &mut W) -> PolarsResult<()> {
+    let columns = df.get_columns();
+
+    let header: Vec<String> = columns
+        .iter()
+        .map(|column| markdown_cell(AnyValue::String(column.name())))
+        .collect();
+    let alignment: Vec<&str> = columns
+        .iter()
+        .map(|column| {
+            if column.dtype().is_numeric() {
+                "---:"
+            } else {
+                "---"
+            }
+        })
+        .collect();
+    writeln!(writer, "| {} |", header.join(" | "))?;
+    writeln!(writer, "| {} |", alignment.join(" | "))?;
+
+    for row in 0..df.height() {
+        let cells = columns
+            .iter()
+            .map(|column| column.get(row).map(markdown_cell))
+            .collect::<PolarsResult<Vec<String>>>()?;
+        writeln!(writer, "| {} |", cells.join(" | "))?;
+    }
+
+    Ok(())
+}
+
 /// Printing and saving the analysis DataFrames.
 pub fn print_and_save(
     df: &mut DataFrame,
     analysis_folder_path: &Path,
     file_name: &str,
     table_name: &str,
-) {
+    format: ResultFormat,
+) -> Result<()> {
     info!("{}: {:?}", table_name, df);
 
     let analysis_file_path = analysis_folder_path.join(Path::new(file_name));
 
-    create_dir_all(analysis_folder_path).expect("Failed to create analysis result folder.");
+    create_dir_all(analysis_folder_path).map_err(|e| Error::io(analysis_folder_path, e))?;
+
+    let mut file = BufWriter::new(
+        File::create(&analysis_file_path).map_err(|e| Error::io(&analysis_file_path, e))?,
+    );
+
+    match format {
+        ResultFormat::Csv | ResultFormat::Tsv => CsvWriter::new(&mut file)
+            .include_header(true)
+            .with_separator(if format == ResultFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            })
+            .finish(df),
+        ResultFormat::Json | ResultFormat::Ndjson => JsonWriter::new(&mut file)
+            .with_json_format(if format == ResultFormat::Json {
+                JsonFormat::Json
+            } else {
+                JsonFormat::JsonLines
+            })
+            .finish(df),
+        ResultFormat::Parquet => ParquetWriter::new(&mut file).finish(df).map(|_| ()),
+        ResultFormat::Markdown => write_markdown(df, &mut file),
+    }
+    .and_then(|_| Ok(file.flush()?))?;
 
-    CsvWriter::new(&mut std::fs::File::create(analysis_file_path).expect("Failed to create file"))
-        .include_header(true)
-        .with_separator(b',')
-        .finish(df)
-        .expect("Failed to write df.");
+    Ok(())
 }
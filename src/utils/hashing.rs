@@ -1,25 +1,193 @@
 use hex;
-use ring::digest::{Context, SHA256};
+use ring::digest::{Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
 
-pub fn hash_file(file_path: &Path) -> Result<String, std::io::Error> {
-    let file = File::open(file_path).expect("Failed to read file");
+/// Files are read in chunks of this size. Large reads keep fast hashes from waiting on the disk.
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
 
-    let mut reader = BufReader::new(file);
-    let mut context = Context::new(&SHA256);
-    let mut buffer = [0; 1024];
+/// Hash functions available for file contents.
+/// SHA256 and BLAKE3 are cryptographic; SHA-1, MD5 and CRC32 are there to match existing checksum files;
+/// xxh3 is the fastest, and good enough to find duplicates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+    Xxh3,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    /// Names as accepted on the command line and stored in the cache.
+    pub const NAMES: [&'static str; 6] = ["sha256", "sha1", "md5", "blake3", "xxh3", "crc32"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Crc32 => "crc32",
+        }
+    }
+
+    fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Ring(Context::new(&SHA256)),
+            HashAlgorithm::Sha1 => Hasher::Ring(Context::new(&SHA1_FOR_LEGACY_USE_ONLY)),
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::default()),
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "md5" => Ok(HashAlgorithm::Md5),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            _ => Err(format!("Unknown hash algorithm: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Running state of one of the hash functions.
+enum Hasher {
+    Ring(Context),
+    Md5(md5::Context),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Ring(context) => context.update(data),
+            Hasher::Md5(context) => context.consume(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(data),
+            Hasher::Crc32(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Lowercase hex digest, as printed by `sha256sum`, `b3sum`, `xxhsum -H3` and friends.
+    fn finish(self) -> String {
+        match self {
+            Hasher::Ring(context) => hex::encode(context.finish().as_ref()),
+            Hasher::Md5(context) => hex::encode(context.finalize().0),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
+
+pub fn hash_file(file_path: &Path, algorithm: HashAlgorithm) -> Result<String, std::io::Error> {
+    let mut file = File::open(file_path)?;
+
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
 
     loop {
-        let count = reader.read(&mut buffer)?;
+        let count = file.read(&mut buffer)?;
         if count == 0 {
             break;
         }
-        context.update(&buffer[..count]);
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Settings for quick hashes, which only read a few blocks of each file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuickHash {
+    /// Bytes read from the start and from the end of the file, and per sample.
+    pub block_size: u64,
+    /// Blocks read at evenly spaced offsets between the first and last block.
+    pub samples: u64,
+}
+
+impl Default for QuickHash {
+    fn default() -> Self {
+        QuickHash {
+            block_size: 64 * 1024,
+            samples: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for QuickHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} KiB blocks, {} samples",
+            self.block_size / 1024,
+            self.samples
+        )
+    }
+}
+
+/// Hashes the file size, the first and last `block_size` bytes, and any sampled blocks in between.
+/// Files that differ there are certainly different, so only files with equal quick hashes need a full hash.
+/// Files no larger than all blocks together are hashed completely.
+pub fn quick_hash_file(
+    file_path: &Path,
+    algorithm: HashAlgorithm,
+    quick_hash: QuickHash,
+) -> Result<String, std::io::Error> {
+    let mut file = File::open(file_path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = algorithm.hasher();
+    hasher.update(&size.to_le_bytes());
+
+    let block_size = quick_hash.block_size;
+    let block_count = quick_hash.samples + 2;
+
+    let offsets: Vec<u64> = if size <= block_size * block_count {
+        vec![0]
+    } else {
+        // First block, samples spread evenly over the rest, and the last block.
+        (0..block_count)
+            .map(|block| block * (size - block_size) / (block_count - 1))
+            .collect()
+    };
+    let read_size = if offsets.len() == 1 { size } else { block_size };
+
+    let mut buffer = vec![0; read_size as usize];
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+        hasher.update(&buffer);
     }
 
-    Ok(hex::encode(context.finish().as_ref()))
+    Ok(hasher.finish())
 }
 
 pub fn hash_iterable<I, T>(iterable: I) -> String
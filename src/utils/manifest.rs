@@ -0,0 +1,246 @@
+use polars::prelude::*;
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::path_data::FileType;
+use crate::utils::hashing::HashAlgorithm;
+
+/// A line of a checksum manifest: the hash and the path relative to the manifest root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub hash: String,
+    pub path: String,
+}
+
+/// The hash algorithm of every hashed file in the cache. A manifest can only have one.
+pub fn cache_hash_algorithm(df: &DataFrame) -> Result<HashAlgorithm> {
+    let algorithms = df.column("hash_algorithm")?.drop_nulls().unique()?;
+
+    match algorithms.len() {
+        0 => Err(Error::InvalidCache(
+            "the cache has no hashes, index it with -H".to_string(),
+        )),
+        1 => algorithms
+            .str()?
+            .get(0)
+            .and_then(|algorithm| algorithm.parse().ok())
+            .ok_or_else(|| Error::InvalidCache("unknown hash algorithm in the cache".to_string())),
+        _ => Err(Error::InvalidCache(
+            "the cache mixes hash algorithms".to_string(),
+        )),
+    }
+}
+
+/// Fails if any file in the cache has no hash, e.g. when it was indexed with `-D`, which only
+/// hashes files of the same size. Otherwise those files would be left out without a word.
+pub fn check_fully_hashed(df: &DataFrame) -> Result<()> {
+    let unhashed = df
+        .clone()
+        .lazy()
+        .filter(
+            col("file_type")
+                .eq(lit(FileType::File.as_str()))
+                .and(col("hash").is_null()),
+        )
+        .select([col("path")])
+        .collect()?;
+
+    if unhashed.height() == 0 {
+        return Ok(());
+    }
+    Err(Error::InvalidCache(format!(
+        "{} files in the cache have no hash, such as {:?}: index the folder again with -H",
+        unhashed.height(),
+        unhashed.column("path")?.str()?.get(0).unwrap_or_default()
+    )))
+}
+
+/// Files of the cache as manifest entries, relative to `root` and sorted by path.
+/// Every file needs a hash, see `check_fully_hashed`.
+pub fn manifest_entries(df: &DataFrame, root: &Path) -> Result<Vec<ManifestEntry>> {
+    check_fully_hashed(df)?;
+
+    let paths = df.column("path")?.str()?;
+    let hashes = df.column("hash")?.str()?;
+
+    let mut entries: Vec<ManifestEntry> = paths
+        .into_iter()
+        .zip(hashes)
+        .filter_map(|(path, hash)| {
+            let relative = Path::new(path?).strip_prefix(root).ok()?;
+            Some(ManifestEntry {
+                hash: hash?.to_string(),
+                path: relative.to_string_lossy().into_owned(),
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Writes the entries in the format of `sha256sum`, which `b3sum`, `md5sum` and friends share.
+/// As in those tools, paths with a backslash or newline get escaped, marked by a leading backslash.
+pub fn write_manifest<W: Write>(entries: &[ManifestEntry], writer: &mut W) -> std::io::Result<()> {
+    for entry in entries {
+        if entry.path.contains(['\\', '\n']) {
+            let escaped = entry.path.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(writer, "\\{}  {}", entry.hash, escaped)?;
+        } else {
+            writeln!(writer, "{}  {}", entry.hash, entry.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a manifest written by `write_manifest` or `sha256sum` and similar tools,
+/// in text (`hash  path`) or binary (`hash *path`) mode. Empty lines and comments are skipped.
+pub fn read_manifest<R: BufRead>(reader: R) -> Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| {
+            Error::Manifest(format!(
+                "Failed to read manifest line {}: {}",
+                number + 1,
+                e
+            ))
+        })?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line.as_str()),
+        };
+
+        // Hashes have no spaces, and the mode marker after them is a space or a `*`.
+        let (hash, path) = line
+            .split_once(' ')
+            .and_then(|(hash, rest)| Some((hash, rest.strip_prefix([' ', '*'])?)))
+            .ok_or_else(|| {
+                Error::Manifest(format!("Invalid manifest line {}: {}", number + 1, line))
+            })?;
+
+        let path = if escaped {
+            unescape(path)
+        } else {
+            path.to_string()
+        };
+
+        entries.push(ManifestEntry {
+            hash: hash.to_lowercase(),
+            path,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Undoes the escaping of backslashes and newlines in manifest paths.
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Guesses the hash algorithm of a manifest from the length of its hashes.
+/// SHA256 and BLAKE3 hashes have the same length, so SHA256 is assumed.
+pub fn guess_hash_algorithm(entries: &[ManifestEntry]) -> Option<HashAlgorithm> {
+    match entries.first()?.hash.len() {
+        64 => Some(HashAlgorithm::Sha256),
+        40 => Some(HashAlgorithm::Sha1),
+        32 => Some(HashAlgorithm::Md5),
+        16 => Some(HashAlgorithm::Xxh3),
+        8 => Some(HashAlgorithm::Crc32),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str, path: &str) -> ManifestEntry {
+        ManifestEntry {
+            hash: hash.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip_with_escaped_paths() {
+        let entries = vec![
+            entry("aa11", "plain.txt"),
+            entry("bb22", "two  spaces"),
+            entry("cc33", "back\\slash"),
+            entry("dd44", "new\nline"),
+            entry("ee55", "both\\n\nof them"),
+            entry("ff66", "*star"),
+        ];
+
+        let mut written = Vec::new();
+        write_manifest(&entries, &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+
+        assert!(written.contains("aa11  plain.txt\n"));
+        assert!(written.contains("\\cc33  back\\\\slash\n"));
+        assert!(written.contains("\\dd44  new\\nline\n"));
+        assert_eq!(read_manifest(written.as_bytes()).unwrap(), entries);
+    }
+
+    #[test]
+    fn reads_binary_mode_comments_and_uppercase() {
+        let manifest = "# made by hand\n\nAA11 *a  b\nbb22  *star\n\\cc33 *back\\\\slash\n";
+
+        assert_eq!(
+            read_manifest(manifest.as_bytes()).unwrap(),
+            vec![
+                entry("aa11", "a  b"),
+                entry("bb22", "*star"),
+                entry("cc33", "back\\slash"),
+            ]
+        );
+    }
+
+    #[test]
+    fn caches_need_a_hash_for_every_file() {
+        let df = df!(
+            "path" => ["/data/a", "/data/b", "/data/link", "/data/sub"],
+            "file_type" => ["file", "file", "symlink", "folder"],
+            "hash" => [Some("aa11"), None, None, None]
+        )
+        .unwrap();
+
+        assert!(check_fully_hashed(&df.head(Some(1))).is_ok());
+        assert!(check_fully_hashed(&df.slice(2, 2)).is_ok());
+        assert!(matches!(
+            check_fully_hashed(&df),
+            Err(Error::InvalidCache(message)) if message.contains("/data/b")
+        ));
+    }
+
+    #[test]
+    fn rejects_lines_without_a_path() {
+        assert!(read_manifest("aa11\n".as_bytes()).is_err());
+        assert!(read_manifest("aa11 path\n".as_bytes()).is_err());
+    }
+}
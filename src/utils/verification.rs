@@ -0,0 +1,206 @@
+use polars::prelude::*;
+use rayon::prelude::*;
+
+#[allow(unused)]
+use log::{error, info, warn};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Result;
+use crate::indexing::cache_metadata::{recorded_root, walk_options};
+use crate::indexing::folder_index::index_root;
+use crate::indexing::index_creation::create_index;
+use crate::indexing::index_options::IndexOptions;
+use crate::path_data::FileType;
+use crate::utils::file_operations::{folder_index_file_path, load_cache_metadata};
+use crate::utils::hashing::{hash_file, HashAlgorithm};
+use crate::utils::manifest::{check_fully_hashed, ManifestEntry};
+
+/// Outcome of re-hashing the files in a cache or manifest.
+#[derive(Debug, Default)]
+pub struct VerificationReport {
+    /// Files whose hash still matches.
+    pub verified: usize,
+    /// Files whose contents changed since they were indexed.
+    pub changed: Vec<String>,
+    /// Files that no longer exist or can't be read.
+    pub missing: Vec<String>,
+    /// Files on disk that are not in the cache or manifest.
+    pub extra: Vec<String>,
+}
+
+impl VerificationReport {
+    /// Whether every file still matches its hash, and no files were added.
+    pub fn is_ok(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// A file to re-hash, reported under `name`.
+struct ExpectedHash {
+    name: String,
+    path: PathBuf,
+    hash: String,
+    algorithm: HashAlgorithm,
+}
+
+/// Re-hashes every file in the cache, using the algorithm it was hashed with, and compares the results.
+/// Fails if any file has no hash, as it could not be verified.
+/// Files in the indexed folder that are not in the cache at all are reported as extra,
+/// except the cache itself if it is saved in that folder. The folder is walked with the filter,
+/// symlink and filesystem options the cache was created with, so skipped files are not extra.
+/// Paths are resolved against the root recorded in the cache, so it can be verified from any folder.
+pub fn verify_cache(df: &DataFrame, cache_file: &Path) -> Result<VerificationReport> {
+    check_fully_hashed(df)?;
+
+    let key_values = load_cache_metadata(cache_file)?;
+    let indexed_root = PathBuf::from(index_root(df)?);
+    let root = recorded_root(&key_values).unwrap_or_else(|| indexed_root.clone());
+    let resolve = |path: &str| {
+        let path = Path::new(path);
+        root.join(path.strip_prefix(&indexed_root).unwrap_or(path))
+    };
+
+    let paths = df.column("path")?.str()?;
+    let hashes = df.column("hash")?.str()?;
+    let hash_algorithms = df.column("hash_algorithm")?.str()?;
+
+    let expected: Vec<ExpectedHash> = paths
+        .into_iter()
+        .zip(hashes)
+        .zip(hash_algorithms)
+        .filter_map(|((path, hash), algorithm)| {
+            let path = resolve(path?);
+            Some(ExpectedHash {
+                name: path.to_string_lossy().into_owned(),
+                path,
+                hash: hash?.to_string(),
+                algorithm: algorithm?.parse().ok()?,
+            })
+        })
+        .collect();
+
+    let mut report = verify_hashes(&expected);
+
+    let options = walk_options(&key_values, &root)?;
+    let known: HashSet<PathBuf> = paths.into_iter().flatten().map(resolve).collect();
+    let skip = [cache_file.to_path_buf(), folder_index_file_path(cache_file)];
+    report.extra = extra_files(&root, &options, &known, &skip)?
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+
+    Ok(report)
+}
+
+/// Re-hashes the files of a manifest, with paths relative to `root`.
+/// Files below `root` missing from the manifest are reported as extra, except the manifest itself.
+pub fn verify_manifest(
+    entries: &[ManifestEntry],
+    root: &Path,
+    algorithm: HashAlgorithm,
+    manifest_file: &Path,
+) -> Result<VerificationReport> {
+    let expected: Vec<ExpectedHash> = entries
+        .iter()
+        .map(|entry| ExpectedHash {
+            name: entry.path.clone(),
+            path: root.join(&entry.path),
+            hash: entry.hash.clone(),
+            algorithm,
+        })
+        .collect();
+
+    let mut report = verify_hashes(&expected);
+
+    let known: HashSet<PathBuf> = expected.into_iter().map(|entry| entry.path).collect();
+    report.extra = extra_files(
+        root,
+        &IndexOptions::default(),
+        &known,
+        &[manifest_file.to_path_buf()],
+    )?
+    .iter()
+    .map(|path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    })
+    .collect();
+
+    Ok(report)
+}
+
+/// Re-hashes the files in parallel and compares them to the expected hashes.
+fn verify_hashes(expected: &[ExpectedHash]) -> VerificationReport {
+    info!("Verifying {} files", expected.len());
+
+    // Whether each file is unchanged, or None if it can't be read.
+    let results: Vec<Option<bool>> = expected
+        .par_iter()
+        .map(|expected| {
+            if !expected.path.is_file() {
+                return None;
+            }
+            hash_file(&expected.path, expected.algorithm)
+                .ok()
+                .map(|actual| actual == expected.hash)
+        })
+        .collect();
+
+    let mut report = VerificationReport::default();
+    for (expected, result) in expected.iter().zip(results) {
+        match result {
+            Some(true) => report.verified += 1,
+            Some(false) => report.changed.push(expected.name.clone()),
+            None => report.missing.push(expected.name.clone()),
+        }
+    }
+
+    report
+}
+
+/// Files below `root` that are not in `known` or `skip`, sorted. The folder is walked like when
+/// indexing with `options`, so the same paths are skipped.
+fn extra_files(
+    root: &Path,
+    options: &IndexOptions,
+    known: &HashSet<PathBuf>,
+    skip: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    let skip: HashSet<PathBuf> = skip
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+
+    let files = Mutex::new(Vec::new());
+    let summary = create_index(root, options, None, |batch| {
+        files.lock().unwrap().extend(
+            batch
+                .into_iter()
+                .filter(|entry| entry.file_type == FileType::File)
+                .map(|entry| entry.path),
+        );
+        true
+    })?;
+    for error in summary.errors {
+        warn!("Failed to read {}", error);
+    }
+
+    let mut extra: Vec<PathBuf> = files
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter(|path| !known.contains(path))
+        .filter(|path| {
+            path.canonicalize()
+                .map_or(true, |canonical| !skip.contains(&canonical))
+        })
+        .collect();
+
+    extra.sort();
+    Ok(extra)
+}